@@ -0,0 +1,64 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use shogi_core::{PartialPosition, ToUsi};
+use shogi_usi_parser::FromUsi;
+use std::process;
+use std::time::Instant;
+use yasai::reference;
+use yasai::Position;
+
+const USAGE: &str = "usage: movegen_diff <games> [--seed <n>] [--sfen <sfen>]";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut games = None;
+    let mut seed = 0;
+    let mut sfen = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                seed = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(usage)
+            }
+            "--sfen" => sfen = args.next(),
+            _ => games = Some(arg.parse().unwrap_or_else(|_| usage())),
+        }
+    }
+    let games = games.unwrap_or_else(usage);
+    let pos = match sfen {
+        Some(sfen) => {
+            let sfen = sfen.strip_prefix("sfen ").unwrap_or(&sfen);
+            match PartialPosition::from_usi(&format!("sfen {sfen}")) {
+                Ok(partial) => Position::new(partial),
+                Err(err) => {
+                    eprintln!("invalid sfen: {err:?}");
+                    process::exit(1);
+                }
+            }
+        }
+        None => Position::default(),
+    };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let now = Instant::now();
+    match reference::random_playouts(&pos, &mut rng, games, 512) {
+        Ok(count) => println!("{count} positions agreed in {:?}", now.elapsed()),
+        Err(mismatch) => {
+            println!("sfen {}", mismatch.sfen);
+            for m in mismatch.missing {
+                println!("missing: {}", m.to_usi_owned());
+            }
+            for m in mismatch.unexpected {
+                println!("unexpected: {}", m.to_usi_owned());
+            }
+            process::exit(1);
+        }
+    }
+}
+
+fn usage<T>() -> T {
+    println!("{USAGE}");
+    process::exit(1);
+}
@@ -1,47 +1,76 @@
-use shogi_core::ToUsi;
+use shogi_core::{PartialPosition, ToUsi};
+use shogi_usi_parser::FromUsi;
 use std::process;
 use std::time::Instant;
+use yasai::perft::{self, PerftTable};
 use yasai::Position;
 
+const USAGE: &str = "usage: perft <depth> [--sfen <sfen>] [--threads <n>] [--hash <mb>] [--stats]";
+
 fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    if let Some(depth) = args.get(1).and_then(|s| s.parse().ok()) {
-        let mut pos = Position::default();
-        assert_eq!(30, pos.legal_moves().len());
-        let now = Instant::now();
-        let total = perft(&mut pos, depth, true);
-        let duration = now.elapsed();
-        println!();
-        println!("Time duration: {:?}", duration);
-        println!(
-            "Searched: {total} nodes: {} nps",
-            (total as u128) * 1_000_000_000 / duration.as_nanos()
-        );
-    } else {
-        println!("usage: perft <depth>");
-        process::exit(1);
+    let mut args = std::env::args().skip(1);
+    let mut depth = None;
+    let mut sfen = None;
+    let mut threads = 1;
+    let mut hash = 0;
+    let mut stats = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sfen" => sfen = args.next(),
+            "--threads" => {
+                threads = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(usage)
+            }
+            "--hash" => {
+                hash = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(usage)
+            }
+            "--stats" => stats = true,
+            _ => depth = Some(arg.parse().ok().filter(|&d| d > 0).unwrap_or_else(usage)),
+        }
     }
-}
+    let depth = depth.unwrap_or_else(usage);
+    let pos = match sfen {
+        Some(sfen) => {
+            let sfen = sfen.strip_prefix("sfen ").unwrap_or(&sfen);
+            match PartialPosition::from_usi(&format!("sfen {sfen}")) {
+                Ok(partial) => Position::new(partial),
+                Err(err) => {
+                    eprintln!("invalid sfen: {err:?}");
+                    process::exit(1);
+                }
+            }
+        }
+        None => Position::default(),
+    };
 
-fn perft(pos: &mut Position, depth: usize, is_root: bool) -> usize {
-    let mut ret = 0;
-    for m in pos.legal_moves() {
-        let count = if depth <= 1 {
-            1
-        } else {
-            pos.do_move(m);
-            let ret = if depth == 2 {
-                pos.legal_moves().len()
-            } else {
-                perft(pos, depth - 1, false)
-            };
-            pos.undo_move(m);
-            ret
-        };
-        ret += count;
-        if is_root {
+    let now = Instant::now();
+    let total = if stats {
+        let stats = perft::stats(&mut pos.clone(), depth);
+        println!("{stats:#?}");
+        stats.nodes
+    } else {
+        let table = (hash > 0).then(|| PerftTable::new(hash));
+        let divided = perft::divide(&pos, depth, threads, table.as_ref());
+        for (m, count) in &divided {
             println!("{}: {count}", m.to_usi_owned());
         }
-    }
-    ret
+        divided.iter().map(|(_, count)| count).sum()
+    };
+    let duration = now.elapsed();
+    println!();
+    println!("Time duration: {:?}", duration);
+    println!(
+        "Searched: {total} nodes: {} nps",
+        (total as u128) * 1_000_000_000 / duration.as_nanos().max(1)
+    );
+}
+
+fn usage<T>() -> T {
+    println!("{USAGE}");
+    process::exit(1);
 }
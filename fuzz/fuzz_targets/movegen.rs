@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shogi_core::ToUsi;
+use yasai::{reference, Position};
+
+// Each pair of bytes chooses the next move from the legal ones.
+fuzz_target!(|data: &[u8]| {
+    let mut pos = Position::default();
+    for chunk in data.chunks_exact(2) {
+        if let Err(mismatch) = reference::compare(&pos) {
+            let moves = |moves: Vec<_>| {
+                moves
+                    .iter()
+                    .map(|m: &shogi_core::Move| m.to_usi_owned())
+                    .collect::<Vec<_>>()
+            };
+            panic!(
+                "sfen {}: missing {:?}, unexpected {:?}",
+                mismatch.sfen,
+                moves(mismatch.missing),
+                moves(mismatch.unexpected)
+            );
+        }
+        let moves = pos.legal_moves();
+        if moves.is_empty() {
+            break;
+        }
+        let i = usize::from(u16::from_le_bytes([chunk[0], chunk[1]])) % moves.len();
+        pos.do_move(moves[i]);
+    }
+});
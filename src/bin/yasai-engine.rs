@@ -0,0 +1,418 @@
+//! A USI engine running [`yasai::search`] with the material evaluation, and [`yasai::tsume`] for
+//! `go mate`.
+//!
+//! ```shell
+//! cargo run --release --bin yasai-engine
+//! ```
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use yasai::search::{Limits, MaterialEvaluator, SearchResult};
+use yasai::smp::ThreadPool;
+use yasai::tsume::{Solution, Solver};
+use yasai::tt::TranspositionTable;
+use yasai::usi::{self, BestMove, Checkmate, GoParams, Info, MateTime, Score};
+use yasai::Position;
+
+const DEFAULT_HASH_MB: usize = 16;
+const DEFAULT_THREADS: usize = 1;
+/// Time reserved for the communication with the GUI.
+const MARGIN: Duration = Duration::from_millis(100);
+
+/// Time control of a search, shared between the engine, the search thread and the timer thread.
+struct Clock {
+    start: Instant,
+    stop: Arc<AtomicBool>,
+    /// Whether to keep searching until `stop` or `ponderhit`.
+    pondering: AtomicBool,
+    /// Milliseconds from `start` to stop, or `u64::MAX` for no limit.
+    deadline: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Clock {
+    fn is_over(&self) -> bool {
+        !self.pondering.load(Ordering::Relaxed)
+            && self.start.elapsed().as_millis() as u64 >= self.deadline.load(Ordering::Relaxed)
+    }
+}
+
+/// Sets [`Clock::finished`] when dropped, so that the timer thread ends even if the search panics.
+struct Finish<'a>(&'a Clock);
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.0.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the time to spend for the move or `go mate`, or `None` if there is no time limit.
+fn time_budget(pos: &Position, params: &GoParams) -> Option<Duration> {
+    let c = pos.side_to_move();
+    let budget = match params.mate {
+        Some(MateTime::Limit(time)) => time,
+        Some(MateTime::Infinite) => return None,
+        None if params.infinite || (params.time(c).is_none() && params.byoyomi.is_none()) => {
+            return None
+        }
+        None => {
+            let time = params.time(c).unwrap_or_default();
+            let inc = params.inc(c).unwrap_or_default();
+            let byoyomi = params.byoyomi.unwrap_or_default();
+            (time / 30 + inc + byoyomi).min(time + inc + byoyomi)
+        }
+    };
+    Some(budget.saturating_sub(MARGIN).max(Duration::from_millis(10)))
+}
+
+fn info(result: &SearchResult, elapsed: Duration, hashfull: u32) -> Info {
+    Info {
+        depth: Some(result.depth),
+        time: Some(elapsed),
+        nodes: Some(result.nodes),
+        nps: Some(result.nodes * 1000 / (elapsed.as_millis() as u64).max(1)),
+        hashfull: Some(hashfull),
+        score: Some(match result.mate_ply() {
+            Some(ply) => Score::Mate(ply),
+            None => Score::Cp(result.score),
+        }),
+        pv: result.pv.clone(),
+        ..Default::default()
+    }
+}
+
+/// Searches the position, which sends `info` for each depth and `bestmove` at last.
+fn think(
+    pool: &mut ThreadPool<MaterialEvaluator>,
+    pos: Position,
+    limits: Limits,
+    clock: &Clock,
+    out: &Sender<String>,
+) {
+    let tt = Arc::clone(pool.table());
+    let result = pool.search_with(&pos, &limits, |result| {
+        let _ = out.send(info(result, clock.start.elapsed(), tt.hashfull()).to_string());
+    });
+    // `bestmove` must wait for `stop` or `ponderhit` while pondering
+    while clock.pondering.load(Ordering::Relaxed) && !clock.stop.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let bestmove = match result.best_move {
+        Some(m) => BestMove::Move(m, result.pv.get(1).copied()),
+        None => BestMove::Resign,
+    };
+    let _ = out.send(bestmove.to_string());
+}
+
+/// Searches for a checkmate by the side to move, which sends `checkmate` at last.
+fn solve_mate(pos: Position, nodes: Option<u64>, clock: &Clock, out: &Sender<String>) {
+    let mut solver = Solver::new(nodes.unwrap_or(u64::MAX));
+    solver.set_stop(Arc::clone(&clock.stop));
+    let checkmate = match solver.solve(&pos) {
+        Solution::Mate(moves) => Checkmate::Mate(moves),
+        Solution::NoMate => Checkmate::NoMate,
+        Solution::Unknown => Checkmate::Timeout,
+    };
+    let _ = out.send(checkmate.to_string());
+}
+
+/// Search running in another thread, which gives the thread pool back when joined.
+struct Thinking {
+    handle: JoinHandle<Option<ThreadPool<MaterialEvaluator>>>,
+    clock: Arc<Clock>,
+    budget: Option<Duration>,
+}
+
+struct Engine {
+    pos: Position,
+    hash_mb: usize,
+    threads: usize,
+    pool: Option<ThreadPool<MaterialEvaluator>>,
+    searching: Option<Thinking>,
+    out: Sender<String>,
+}
+
+impl Engine {
+    fn new(out: Sender<String>) -> Self {
+        Self {
+            pos: Position::default(),
+            hash_mb: DEFAULT_HASH_MB,
+            threads: DEFAULT_THREADS,
+            pool: None,
+            searching: None,
+            out,
+        }
+    }
+    fn send(&self, s: &str) {
+        let _ = self.out.send(s.to_string());
+    }
+    fn pool(&mut self) -> &mut ThreadPool<MaterialEvaluator> {
+        let (hash_mb, threads) = (self.hash_mb, self.threads);
+        self.pool.get_or_insert_with(|| {
+            let tt = Arc::new(TranspositionTable::new(hash_mb));
+            ThreadPool::new(MaterialEvaluator, threads, tt)
+        })
+    }
+    /// Stops the search, if any, and waits for `bestmove` or `checkmate`.
+    fn stop(&mut self) {
+        if let Some(thinking) = self.searching.take() {
+            thinking.clock.stop.store(true, Ordering::Relaxed);
+            self.pool = thinking.handle.join().ok().flatten();
+        }
+    }
+    /// Handles a command, and returns `false` for `quit`.
+    fn handle(&mut self, line: &str) -> bool {
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        match command {
+            "usi" => {
+                self.send(concat!("id name yasai-engine ", env!("CARGO_PKG_VERSION")));
+                self.send("id author yasai developers");
+                self.send(&format!(
+                    "option name USI_Hash type spin default {DEFAULT_HASH_MB} min 1 max 65536"
+                ));
+                self.send(&format!(
+                    "option name Threads type spin default {DEFAULT_THREADS} min 1 max 256"
+                ));
+                self.send("option name USI_Ponder type check default false");
+                self.send("usiok");
+            }
+            "isready" => {
+                self.stop();
+                self.pool();
+                self.send("readyok");
+            }
+            "setoption" => {
+                self.stop();
+                let mut tokens = args.split_whitespace();
+                let (Some("name"), Some(name), Some("value"), Some(value)) =
+                    (tokens.next(), tokens.next(), tokens.next(), tokens.next())
+                else {
+                    return true;
+                };
+                match (name, value.parse::<usize>()) {
+                    ("USI_Hash", Ok(mb)) => {
+                        self.hash_mb = mb.max(1);
+                        self.pool = None;
+                    }
+                    ("Threads", Ok(threads)) => {
+                        self.threads = threads.max(1);
+                        if let Some(pool) = self.pool.as_mut() {
+                            pool.set_threads(self.threads);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "usinewgame" => {
+                self.stop();
+                if let Some(pool) = self.pool.as_mut() {
+                    pool.clear();
+                }
+            }
+            "position" => {
+                self.stop();
+                match usi::parse_position(args) {
+                    Some(pos) => self.pos = pos,
+                    None => self.send("info string invalid position"),
+                }
+            }
+            "go" => {
+                self.stop();
+                let Some(params) = GoParams::parse(args) else {
+                    self.send("info string invalid go command");
+                    return true;
+                };
+                let budget = time_budget(&self.pos, &params);
+                let clock = Arc::new(Clock {
+                    start: Instant::now(),
+                    stop: Arc::new(AtomicBool::new(false)),
+                    pondering: AtomicBool::new(params.ponder || params.infinite),
+                    deadline: AtomicU64::new(budget.map_or(u64::MAX, |d| d.as_millis() as u64)),
+                    finished: AtomicBool::new(false),
+                });
+                let limits = Limits {
+                    depth: params.depth,
+                    nodes: params.nodes,
+                    time: None,
+                    stop: Arc::clone(&clock.stop),
+                };
+                let timer_clock = Arc::clone(&clock);
+                thread::spawn(move || {
+                    while !timer_clock.finished.load(Ordering::Relaxed) {
+                        if timer_clock.is_over() {
+                            timer_clock.stop.store(true, Ordering::Relaxed);
+                        }
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                });
+                let pos = self.pos.clone();
+                let out = self.out.clone();
+                let thread_clock = Arc::clone(&clock);
+                let handle = if params.mate.is_some() {
+                    let pool = self.pool.take();
+                    thread::spawn(move || {
+                        let _finish = Finish(&thread_clock);
+                        solve_mate(pos, params.nodes, &thread_clock, &out);
+                        pool
+                    })
+                } else {
+                    self.pool();
+                    let mut pool = self.pool.take().expect("no thread pool");
+                    thread::spawn(move || {
+                        let _finish = Finish(&thread_clock);
+                        think(&mut pool, pos, limits, &thread_clock, &out);
+                        Some(pool)
+                    })
+                };
+                self.searching = Some(Thinking {
+                    handle,
+                    clock,
+                    budget,
+                });
+            }
+            "ponderhit" => {
+                if let Some(Thinking { clock, budget, .. }) = &self.searching {
+                    // the time for the move starts now
+                    let elapsed = clock.start.elapsed().as_millis() as u64;
+                    let deadline = budget.map_or(u64::MAX, |d| elapsed + d.as_millis() as u64);
+                    clock.deadline.store(deadline, Ordering::Relaxed);
+                    clock.pondering.store(false, Ordering::Relaxed);
+                }
+            }
+            "stop" | "gameover" => self.stop(),
+            "quit" => {
+                self.stop();
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+fn main() {
+    let (tx, rx) = mpsc::channel::<String>();
+    let printer = thread::spawn(move || {
+        for line in rx {
+            println!("{line}");
+        }
+    });
+    let mut engine = Engine::new(tx);
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !engine.handle(&line) {
+            break;
+        }
+    }
+    engine.stop();
+    drop(engine);
+    let _ = printer.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+
+    fn run(commands: &[&str]) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        let mut engine = Engine::new(tx);
+        for command in commands {
+            assert!(engine.handle(command));
+        }
+        assert!(!engine.handle("quit"));
+        rx
+    }
+
+    #[test]
+    fn protocol() {
+        let lines = run(&["usi", "isready"]).into_iter().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("id name yasai-engine"));
+        assert!(lines.contains(&String::from("usiok")));
+        assert_eq!(Some(&String::from("readyok")), lines.last());
+    }
+
+    #[test]
+    fn mate_in_one() {
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // P2 *  *  *  *  *  *  *  * -KY
+        // P3 *  *  *  *  *  *  * +FU-FU
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  *  * +OU *  *  *
+        // P+00KI
+        // P-00AL
+        // +
+        let (tx, rx) = mpsc::channel();
+        let mut engine = Engine::new(tx);
+        engine.handle("position sfen 7nk/8l/7Pp/9/9/9/9/9/5K3 b G2r2b3g4s3n3l16p 1");
+        engine.handle("go depth 3");
+        // quitting before the bestmove would stop the search
+        let mut lines = Vec::new();
+        for line in rx.iter() {
+            let bestmove = line.starts_with("bestmove");
+            lines.push(line);
+            if bestmove {
+                break;
+            }
+        }
+        assert!(!engine.handle("quit"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("info depth 1 ") && line.contains("score mate 1")));
+        assert_eq!(Some(&String::from("bestmove G*2b")), lines.last());
+    }
+
+    #[test]
+    fn captures() {
+        // the rook can be captured for free
+        let (tx, rx) = mpsc::channel();
+        let mut engine = Engine::new(tx);
+        engine.handle("position sfen 4k4/9/9/9/9/9/5r3/9/4K2B1 b - 1");
+        engine.handle("go btime 0 wtime 0 byoyomi 300");
+        // stops by itself
+        let bestmove = rx
+            .iter()
+            .find(|line| line.starts_with("bestmove"))
+            .expect("no bestmove");
+        assert!(bestmove.starts_with("bestmove 2i4g"));
+    }
+
+    #[test]
+    fn stop_infinite() {
+        let (tx, rx) = mpsc::channel();
+        let mut engine = Engine::new(tx);
+        engine.handle("setoption name Threads value 2");
+        engine.handle("position startpos");
+        engine.handle("go infinite");
+        // keeps searching after the first depth
+        let info = rx.recv().expect("no info");
+        assert!(info.starts_with("info depth 1 "));
+        assert!(rx.try_iter().all(|line| !line.starts_with("bestmove")));
+        engine.handle("stop");
+        assert!(rx.try_iter().any(|line| line.starts_with("bestmove")));
+    }
+
+    #[test]
+    fn go_mate() {
+        let (tx, rx) = mpsc::channel();
+        let mut engine = Engine::new(tx);
+        engine.handle("position sfen 7nk/8l/7Pp/9/9/9/9/9/5K3 b G2r2b3g4s3n3l16p 1");
+        engine.handle("go mate 10000");
+        assert_eq!(Ok(String::from("checkmate G*2b")), rx.recv());
+        engine.handle("go nodes 1 mate infinite");
+        assert_eq!(Ok(String::from("checkmate timeout")), rx.recv());
+        engine.handle("position startpos");
+        engine.handle("go mate infinite");
+        assert_eq!(Ok(String::from("checkmate nomate")), rx.recv());
+        assert!(!engine.handle("quit"));
+    }
+}
@@ -0,0 +1,126 @@
+/// Writes bits into a fixed 256-bit buffer, least significant bit first.
+pub(crate) struct BitWriter {
+    data: [u8; 32],
+    cursor: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self {
+            data: [0; 32],
+            cursor: 0,
+        }
+    }
+    pub fn write_bit(&mut self, b: bool) {
+        if b && self.cursor < 256 {
+            self.data[self.cursor / 8] |= 1 << (self.cursor % 8);
+        }
+        self.cursor += 1;
+    }
+    pub fn write_bits(&mut self, value: u32, n: usize) {
+        for i in 0..n {
+            self.write_bit(value & (1 << i) != 0);
+        }
+    }
+    /// Returns the buffer only if exactly 256 bits have been written.
+    pub fn finish(self) -> Option<[u8; 32]> {
+        if self.cursor == 256 {
+            Some(self.data)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads bits from a 256-bit buffer, least significant bit first.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8; 32],
+    cursor: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8; 32]) -> Self {
+        Self { data, cursor: 0 }
+    }
+    pub fn read_bit(&mut self) -> Option<bool> {
+        if self.cursor >= 256 {
+            return None;
+        }
+        let b = self.data[self.cursor / 8] & (1 << (self.cursor % 8)) != 0;
+        self.cursor += 1;
+        Some(b)
+    }
+    pub fn read_bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            if self.read_bit()? {
+                value |= 1 << i;
+            }
+        }
+        Some(value)
+    }
+    /// Reads bits one by one until they match one of the `(code, bits)` entries of the table,
+    /// and returns the index of the matched entry.
+    pub fn read_huffman(&mut self, table: &[(u32, usize)]) -> Option<usize> {
+        let max_bits = table
+            .iter()
+            .map(|&(_, bits)| bits)
+            .max()
+            .unwrap_or_default();
+        let mut code = 0;
+        for bits in 1..=max_bits {
+            if self.read_bit()? {
+                code |= 1 << (bits - 1);
+            }
+            if let Some(i) = table.iter().position(|&e| e == (code, bits)) {
+                return Some(i);
+            }
+        }
+        None
+    }
+    pub fn is_end(&self) -> bool {
+        self.cursor == 256
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_bits(0x55, 7);
+        for _ in 0..31 {
+            writer.write_bits(0xa5, 8);
+        }
+        let data = writer.finish().expect("256 bits written");
+        assert_eq!(0xab, data[0]);
+        let mut reader = BitReader::new(&data);
+        assert_eq!(Some(true), reader.read_bit());
+        assert_eq!(Some(0x55), reader.read_bits(7));
+        assert_eq!(Some(0xa5), reader.read_bits(8));
+        assert_eq!(Some(1), reader.read_huffman(&[(0, 2), (1, 1)]));
+        assert_eq!(Some(2), reader.read_huffman(&[(0, 2), (1, 1), (2, 3)]));
+        for _ in 0..236 {
+            assert!(reader.read_bit().is_some());
+        }
+        assert!(reader.is_end());
+        assert_eq!(None, reader.read_bit());
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut writer = BitWriter::new();
+        for _ in 0..255 {
+            writer.write_bit(false);
+        }
+        assert!(writer.finish().is_none());
+        let mut writer = BitWriter::new();
+        for _ in 0..257 {
+            writer.write_bit(true);
+        }
+        assert!(writer.finish().is_none());
+    }
+}
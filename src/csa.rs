@@ -0,0 +1,599 @@
+//! Reader and writer of the [CSA standard file format](http://www2.computer-shogi.org/protocol/record_v22.html).
+//!
+//! ```text
+//! V2.2
+//! N+black
+//! N-white
+//! $EVENT:event
+//! PI
+//! +
+//! +7776FU
+//! T12
+//! -3334FU
+//! T5
+//! 'comment
+//! %TORYO
+//! ```
+//!
+//! The format has no variations: only the main line of a [`Record`] is written.
+use crate::record::{Action, ParseError, ParseErrorKind, Record, RecordMove, SpecialMove};
+use crate::Position;
+use shogi_core::{Color, Hand, Move, PartialPosition, Piece, PieceKind, Square};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Piece codes indexed by `PieceKind::array_index`.
+pub(crate) const PIECE_CODES: [&str; PieceKind::NUM] = [
+    "FU", "KY", "KE", "GI", "KI", "KA", "HI", "OU", "TO", "NY", "NK", "NG", "UM", "RY",
+];
+
+#[rustfmt::skip]
+const SPECIAL_MOVES: [(&str, SpecialMove); 14] = [
+    ("%TORYO",           SpecialMove::Resign),
+    ("%CHUDAN",          SpecialMove::Interrupt),
+    ("%SENNICHITE",      SpecialMove::Repetition),
+    ("%TIME_UP",         SpecialMove::TimeUp),
+    ("%ILLEGAL_MOVE",    SpecialMove::IllegalMove),
+    ("%+ILLEGAL_ACTION", SpecialMove::IllegalAction(Color::Black)),
+    ("%-ILLEGAL_ACTION", SpecialMove::IllegalAction(Color::White)),
+    ("%JISHOGI",         SpecialMove::Jishogi),
+    ("%KACHI",           SpecialMove::EnteringKing),
+    ("%HIKIWAKE",        SpecialMove::Draw),
+    ("%MATTA",           SpecialMove::Matta),
+    ("%TSUMI",           SpecialMove::Mate),
+    ("%FUZUMI",          SpecialMove::NoMate),
+    ("%ERROR",           SpecialMove::Error),
+];
+
+fn parse_piece_kind(s: &str) -> Option<PieceKind> {
+    PIECE_CODES
+        .iter()
+        .position(|&code| code == s)
+        .map(|i| PieceKind::all()[i])
+}
+
+fn parse_color(c: u8) -> Option<Color> {
+    match c {
+        b'+' => Some(Color::Black),
+        b'-' => Some(Color::White),
+        _ => None,
+    }
+}
+
+/// Parses a square such as `77`, or `None` for `00`.
+fn parse_square(s: &[u8]) -> Option<Option<Square>> {
+    match s {
+        b"00" => Some(None),
+        [file, rank] => Square::new(file.wrapping_sub(b'0'), rank.wrapping_sub(b'0')).map(Some),
+        _ => None,
+    }
+}
+
+/// Parses a CSA game record. Only the first game is read if the file contains several of them.
+/// All the moves are checked to be legal.
+pub fn parse(s: &str) -> Result<Record, ParseError> {
+    let mut record = Record::default();
+    let mut initial: Option<PartialPosition> = None;
+    let mut pos: Option<Position> = None;
+    let mut line_number = 0;
+    for (i, line) in s.lines().enumerate() {
+        line_number = i + 1;
+        let error = |kind| ParseError { line: i + 1, kind };
+        let line = line.trim_end_matches('\r');
+        if let Some(comment) = line.strip_prefix('\'') {
+            match record.moves.last_mut() {
+                Some(record_move) => record_move.comments.push(comment.to_string()),
+                None => record.comments.push(comment.to_string()),
+            }
+            continue;
+        }
+        if line == "/" {
+            break;
+        }
+        for statement in line.split(',').filter(|s| !s.is_empty()) {
+            if let Some(pos) = pos.as_mut() {
+                parse_statement(pos, &mut record.moves, statement).map_err(error)?;
+                continue;
+            }
+            match statement.as_bytes() {
+                [b'V', ..] => {}
+                [b'N', c, ..] => {
+                    let c = parse_color(*c).ok_or(error(ParseErrorKind::InvalidHeader))?;
+                    let name = statement
+                        .get(2..)
+                        .ok_or(error(ParseErrorKind::InvalidHeader))?
+                        .to_string();
+                    match c {
+                        Color::Black => record.black_name = Some(name),
+                        Color::White => record.white_name = Some(name),
+                    }
+                }
+                [b'$', ..] => {
+                    let (key, value) = statement[1..]
+                        .split_once(':')
+                        .ok_or(error(ParseErrorKind::InvalidHeader))?;
+                    record.headers.push((key.to_string(), value.to_string()));
+                }
+                [b'P', ..] => {
+                    let partial = initial.get_or_insert_with(PartialPosition::empty);
+                    parse_position_line(partial, statement)
+                        .ok_or(error(ParseErrorKind::InvalidPosition))?;
+                }
+                [c] => {
+                    let c = parse_color(*c).ok_or(error(ParseErrorKind::InvalidPosition))?;
+                    let mut partial = initial.take().unwrap_or_else(PartialPosition::startpos);
+                    partial.side_to_move_set(c);
+                    pos = Some(Position::new(partial.clone()));
+                    record.initial = partial;
+                }
+                _ => return Err(error(ParseErrorKind::InvalidHeader)),
+            }
+        }
+    }
+    // the position without the side to move
+    if initial.is_some() && pos.is_none() {
+        return Err(ParseError {
+            line: line_number,
+            kind: ParseErrorKind::InvalidPosition,
+        });
+    }
+    Ok(record)
+}
+
+fn parse_position_line(partial: &mut PartialPosition, line: &str) -> Option<()> {
+    let bytes = line.as_bytes();
+    match bytes.get(1)? {
+        b'I' => {
+            *partial = PartialPosition::startpos();
+            // pieces removed for handicaps, such as `PI82HI22KA`
+            for item in bytes[2..].chunks(4) {
+                let sq = parse_square(item.get(..2)?)??;
+                let pk = parse_piece_kind(std::str::from_utf8(item.get(2..)?).ok()?)?;
+                if partial.piece_at(sq)?.piece_kind() != pk {
+                    return None;
+                }
+                partial.piece_set(sq, None);
+            }
+        }
+        rank @ b'1'..=b'9' => {
+            // the trailing spaces of empty squares may be trimmed
+            let mut cells = bytes[2..].to_vec();
+            if cells.len() > 27 {
+                return None;
+            }
+            cells.resize(27, b' ');
+            for (i, cell) in cells.chunks(3).enumerate() {
+                let sq = Square::new(9 - i as u8, rank - b'0')?;
+                let piece = match cell {
+                    b" * " => None,
+                    [c, code @ ..] => Some(Piece::new(
+                        parse_piece_kind(std::str::from_utf8(code).ok()?)?,
+                        parse_color(*c)?,
+                    )),
+                    _ => return None,
+                };
+                partial.piece_set(sq, piece);
+            }
+        }
+        &c => {
+            let c = parse_color(c)?;
+            for item in bytes[2..].chunks(4) {
+                let code = std::str::from_utf8(item.get(2..)?).ok()?;
+                match parse_square(item.get(..2)?)? {
+                    Some(sq) => partial.piece_set(sq, Some(Piece::new(parse_piece_kind(code)?, c))),
+                    None if code == "AL" => {
+                        // all the remaining pieces except for the kings
+                        for pk in Hand::all_hand_pieces() {
+                            for _ in 0..remaining(partial, pk) {
+                                let hand = partial.hand_of_a_player_mut(c);
+                                *hand = hand.added(pk)?;
+                            }
+                        }
+                    }
+                    None => {
+                        let hand = partial.hand_of_a_player_mut(c);
+                        *hand = hand.added(parse_piece_kind(code)?)?;
+                    }
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Returns the number of the pieces of the kind which are neither on the board nor in hand.
+fn remaining(partial: &PartialPosition, pk: PieceKind) -> u8 {
+    let total: u8 = match pk {
+        PieceKind::Pawn => 18,
+        PieceKind::Bishop | PieceKind::Rook => 2,
+        _ => 4,
+    };
+    let on_board = Square::all()
+        .filter_map(|sq| partial.piece_at(sq))
+        .filter(|p| p.piece_kind() == pk || p.piece_kind().unpromote() == Some(pk))
+        .count() as u8;
+    let in_hand = Color::all()
+        .iter()
+        .map(|&c| partial.hand_of_a_player(c).count(pk).unwrap_or_default())
+        .sum::<u8>();
+    total.saturating_sub(on_board + in_hand)
+}
+
+fn parse_statement(
+    pos: &mut Position,
+    moves: &mut Vec<RecordMove>,
+    statement: &str,
+) -> Result<(), ParseErrorKind> {
+    if let Some(time) = statement.strip_prefix('T') {
+        let record_move = moves.last_mut().ok_or(ParseErrorKind::InvalidMove)?;
+        record_move.time = Some(parse_time(time).ok_or(ParseErrorKind::InvalidMove)?);
+        return Ok(());
+    }
+    if matches!(
+        moves.last(),
+        Some(RecordMove {
+            action: Action::Special(_),
+            ..
+        })
+    ) {
+        return Err(ParseErrorKind::InvalidMove);
+    }
+    if let Some(&(_, special)) = SPECIAL_MOVES.iter().find(|(code, _)| *code == statement) {
+        moves.push(RecordMove::new(Action::Special(special)));
+        return Ok(());
+    }
+    let m = parse_move(pos, statement).ok_or(ParseErrorKind::InvalidMove)?;
+    if !pos.legal_moves().contains(&m) {
+        return Err(ParseErrorKind::IllegalMove);
+    }
+    pos.do_move(m);
+    moves.push(RecordMove::new(Action::Move(m)));
+    Ok(())
+}
+
+/// Parses a move such as `+7776FU`. The piece kind is the one after the move.
+fn parse_move(pos: &Position, s: &str) -> Option<Move> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 7 || parse_color(bytes[0])? != pos.side_to_move() {
+        return None;
+    }
+    let to = parse_square(&bytes[3..5])??;
+    let pk = parse_piece_kind(&s[5..])?;
+    match parse_square(&bytes[1..3])? {
+        Some(from) => {
+            let moved = pos.piece_at(from)?.piece_kind();
+            let promote = match moved.promote() {
+                Some(promoted) if promoted == pk => true,
+                _ if moved == pk => false,
+                _ => return None,
+            };
+            Some(Move::Normal { from, to, promote })
+        }
+        None => Some(Move::Drop {
+            to,
+            piece: Piece::new(pk, pos.side_to_move()),
+        }),
+    }
+}
+
+/// Parses the time such as `12` or `12.345` (seconds).
+fn parse_time(s: &str) -> Option<Duration> {
+    let (secs, millis) = s.split_once('.').unwrap_or((s, "0"));
+    if millis.len() > 3 {
+        return None;
+    }
+    let millis = millis.parse::<u64>().ok()? * 10_u64.pow(3 - millis.len() as u32);
+    Some(Duration::from_secs(secs.parse().ok()?) + Duration::from_millis(millis))
+}
+
+pub(crate) fn format_move(pos: &Position, m: Move) -> String {
+    let c = if pos.side_to_move() == Color::Black {
+        '+'
+    } else {
+        '-'
+    };
+    let (from, pk) = match m {
+        Move::Normal { from, promote, .. } => {
+            let pk = pos
+                .piece_at(from)
+                .map_or(PieceKind::Pawn, |p| p.piece_kind());
+            let pk = if promote {
+                pk.promote().unwrap_or(pk)
+            } else {
+                pk
+            };
+            (format!("{}{}", from.file(), from.rank()), pk)
+        }
+        Move::Drop { piece, .. } => (String::from("00"), piece.piece_kind()),
+    };
+    format!(
+        "{c}{from}{}{}{}",
+        m.to().file(),
+        m.to().rank(),
+        PIECE_CODES[pk.array_index()]
+    )
+}
+
+/// Serializes the main line of the record in CSA (V2.2).
+///
+/// The moves are assumed to be legal: use [`Record::to_position`] to check them in advance.
+pub fn to_string(record: &Record) -> String {
+    let mut s = String::from("V2.2\n");
+    if let Some(name) = &record.black_name {
+        let _ = writeln!(s, "N+{name}");
+    }
+    if let Some(name) = &record.white_name {
+        let _ = writeln!(s, "N-{name}");
+    }
+    for (key, value) in &record.headers {
+        let _ = writeln!(s, "${key}:{value}");
+    }
+    let initial = &record.initial;
+    let mut startpos = PartialPosition::startpos();
+    startpos.side_to_move_set(initial.side_to_move());
+    if *initial == startpos {
+        s.push_str("PI\n");
+    } else {
+        for rank in 1..=9 {
+            let _ = write!(s, "P{rank}");
+            for file in (1..=9).rev() {
+                match initial.piece_at(Square::new(file, rank).unwrap()) {
+                    Some(p) => {
+                        s.push(if p.color() == Color::Black { '+' } else { '-' });
+                        s.push_str(PIECE_CODES[p.piece_kind().array_index()]);
+                    }
+                    None => s.push_str(" * "),
+                }
+            }
+            s.push('\n');
+        }
+        for (c, prefix) in [(Color::Black, "P+"), (Color::White, "P-")] {
+            let hand = initial.hand_of_a_player(c);
+            if hand == Hand::new() {
+                continue;
+            }
+            s.push_str(prefix);
+            for pk in Hand::all_hand_pieces() {
+                for _ in 0..hand.count(pk).unwrap_or_default() {
+                    let _ = write!(s, "00{}", PIECE_CODES[pk.array_index()]);
+                }
+            }
+            s.push('\n');
+        }
+    }
+    s.push(if initial.side_to_move() == Color::Black {
+        '+'
+    } else {
+        '-'
+    });
+    s.push('\n');
+    for comment in &record.comments {
+        let _ = writeln!(s, "'{comment}");
+    }
+    let mut pos = Position::new(initial.clone());
+    for record_move in &record.moves {
+        match record_move.action {
+            Action::Move(m) => {
+                s.push_str(&format_move(&pos, m));
+                pos.do_move(m);
+            }
+            Action::Special(special) => {
+                let (code, _) = SPECIAL_MOVES
+                    .iter()
+                    .find(|&&(_, sm)| sm == special)
+                    .expect("all the special moves have codes");
+                s.push_str(code);
+            }
+        }
+        s.push('\n');
+        if let Some(time) = record_move.time {
+            match time.subsec_millis() {
+                0 => {
+                    let _ = writeln!(s, "T{}", time.as_secs());
+                }
+                millis => {
+                    let _ = writeln!(s, "T{}.{millis:03}", time.as_secs());
+                }
+            }
+        }
+        for comment in &record_move.comments {
+            let _ = writeln!(s, "'{comment}");
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSA: &str = "\
+'CSA encoding=UTF-8
+V2.2
+N+black
+N-white
+$EVENT:test
+$START_TIME:2022/01/01 10:00:00
+PI
++
++7776FU,T1
+-3334FU
+T2
+'** 30 +8822UM
++8822UM
+T3.5
+-3122GI
+T4
++0045KA
+T65
+'good move
+%TORYO
+";
+
+    #[test]
+    fn parse_csa() {
+        let record = parse(CSA).expect("failed to parse");
+        assert_eq!(Some("black"), record.black_name.as_deref());
+        assert_eq!(Some("white"), record.white_name.as_deref());
+        assert_eq!(
+            vec![
+                (String::from("EVENT"), String::from("test")),
+                (
+                    String::from("START_TIME"),
+                    String::from("2022/01/01 10:00:00")
+                ),
+            ],
+            record.headers
+        );
+        assert_eq!(PartialPosition::startpos(), record.initial);
+        assert_eq!(vec![String::from("CSA encoding=UTF-8")], record.comments);
+        assert_eq!(6, record.moves.len());
+        assert_eq!(Some(Duration::from_secs(1)), record.moves[0].time);
+        assert_eq!(
+            vec![String::from("** 30 +8822UM")],
+            record.moves[1].comments
+        );
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_8H,
+                to: Square::SQ_2B,
+                promote: true,
+            }),
+            record.moves[2].action
+        );
+        assert_eq!(Some(Duration::from_millis(3500)), record.moves[2].time);
+        assert_eq!(
+            Action::Move(Move::Drop {
+                to: Square::SQ_4E,
+                piece: Piece::B_B,
+            }),
+            record.moves[4].action
+        );
+        assert_eq!(vec![String::from("good move")], record.moves[4].comments);
+        assert_eq!(Action::Special(SpecialMove::Resign), record.moves[5].action);
+        assert!(record.to_position().is_some());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let record = parse(CSA).expect("failed to parse");
+        let s = to_string(&record);
+        assert!(s.contains("+8822UM\nT3.500\n-3122GI\nT4\n"));
+        assert_eq!(record, parse(&s).expect("failed to parse"));
+
+        // a played position
+        let mut pos = Position::default();
+        for m in record.moves.iter().filter_map(|rm| rm.action.as_move()) {
+            pos.do_move(m);
+        }
+        assert_eq!(
+            "V2.2\nPI\n+\n+7776FU\n-3334FU\n+8822UM\n-3122GI\n+0045KA\n",
+            to_string(&Record::from_position(&pos).expect("null move"))
+        );
+    }
+
+    #[test]
+    fn positions() {
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // P2 *  *  *  *  *  *  *  * -KY
+        // P3 *  *  *  *  *  *  * +FU-FU
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  *  *  *  *  *  *
+        // P+00KI00KE
+        // P-00AL
+        // +
+        let csa = "\
+P1 *  *  *  *  *  *  * -KE-OU
+P2 *  *  *  *  *  *  *  * -KY
+P3 *  *  *  *  *  *  * +FU-FU
+P4 *  *  *  *  *  *  *  *  *
+P5 *  *  *  *  *  *  *  *  *
+P6 *  *  *  *  *  *  *  *  *
+P7 *  *  *  *  *  *  *  *  *
+P8 *  *  *  *  *  *  *  *  *
+P9 *  *  *  *  *  *  *  *  *
+P+00KI00KE
+P-00AL
++
++2322TO
+-1122OU
+%TSUMI
+";
+        let record = parse(csa).expect("failed to parse");
+        let initial = &record.initial;
+        assert_eq!(Some(Piece::W_N), initial.piece_at(Square::SQ_2A));
+        assert_eq!(Some(1), initial.hand(Piece::B_G));
+        assert_eq!(Some(1), initial.hand(Piece::B_N));
+        assert_eq!(Some(16), initial.hand(Piece::W_P));
+        assert_eq!(Some(2), initial.hand(Piece::W_N));
+        assert_eq!(Some(3), initial.hand(Piece::W_G));
+        assert_eq!(Action::Special(SpecialMove::Mate), record.moves[2].action);
+        assert_eq!(record, parse(&to_string(&record)).expect("failed to parse"));
+
+        // handicap
+        let record = parse("PI82HI22KA\n-\n-3334FU\n").expect("failed to parse");
+        assert_eq!(None, record.initial.piece_at(Square::SQ_8B));
+        assert_eq!(None, record.initial.piece_at(Square::SQ_2B));
+        assert_eq!(Color::White, record.initial.side_to_move());
+        let s = to_string(&record);
+        assert!(
+            s.starts_with("V2.2\nP1-KY-KE-GI-KI-OU-KI-GI-KE-KY\nP2 *  *  *  *  *  *  *  *  * \n")
+        );
+        assert_eq!(record, parse(&s).expect("failed to parse"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |s: &str| parse(s).err();
+        assert_eq!(
+            Some(ParseError {
+                line: 3,
+                kind: ParseErrorKind::IllegalMove
+            }),
+            error("PI\n+\n+7775FU\n")
+        );
+        // wrong side
+        assert_eq!(
+            Some(ParseError {
+                line: 3,
+                kind: ParseErrorKind::InvalidMove
+            }),
+            error("PI\n+\n-3334FU\n")
+        );
+        // moves after the end
+        assert_eq!(
+            Some(ParseError {
+                line: 3,
+                kind: ParseErrorKind::InvalidMove
+            }),
+            error("PI\n+\n%TORYO,+7776FU\n")
+        );
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::InvalidPosition
+            }),
+            error("P1 *  * +XX\n")
+        );
+        // not a color after `N`
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::InvalidHeader
+            }),
+            error("Nあ\n")
+        );
+        // no side to move after the position
+        assert_eq!(
+            Some(ParseError {
+                line: 2,
+                kind: ParseErrorKind::InvalidPosition
+            }),
+            error("PI82HI\nP+00FU\n")
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::csa::{format_move, PIECE_CODES};
+use crate::japanese::{PIECE_CHARS, RANK_CHARS};
+use crate::kif::{hand_to_japanese, BOARD_FRAME, FILES_HEADER};
+use crate::Position;
+use shogi_core::{Color, Hand, Square};
+use std::fmt;
+
+/// ANSI escape sequences of the highlighted squares.
+const HIGHLIGHT_START: &str = "\x1b[7m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// Board diagram of a [`Position`] with options, created by [`Position::diagram`].
+///
+/// By default, it's drawn in kanji as in BOD of KIF, with the pieces in hand, the side to move,
+/// the number of moves and the last move.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagram<'a> {
+    pos: &'a Position,
+    ascii: bool,
+    highlight: bool,
+}
+
+impl<'a> Diagram<'a> {
+    /// Draws the board in CSA instead, with only ASCII characters.
+    pub fn ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+    /// Highlights the destination of the last move and the king in check by ANSI escape sequences.
+    pub fn highlight(mut self, highlight: bool) -> Self {
+        self.highlight = highlight;
+        self
+    }
+    fn is_highlighted(&self, sq: Square) -> bool {
+        let pos = self.pos;
+        self.highlight
+            && (pos.last_move().is_some_and(|m| m.to() == sq)
+                || (pos.in_check() && pos.king_position(pos.side_to_move()) == Some(sq)))
+    }
+    /// Returns the last move with the position before it.
+    fn last_move(&self) -> Option<(Position, shogi_core::Move)> {
+        let m = self.pos.last_move()?;
+        let mut prev = self.pos.clone();
+        prev.undo_move(m);
+        Some((prev, m))
+    }
+    fn fmt_kanji(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = self.pos;
+        writeln!(
+            f,
+            "後手の持駒：{}",
+            hand_to_japanese(pos.hand(Color::White))
+        )?;
+        writeln!(f, "{FILES_HEADER}")?;
+        writeln!(f, "{BOARD_FRAME}")?;
+        for rank in 1..=9 {
+            write!(f, "|")?;
+            for file in (1..=9).rev() {
+                let sq = Square::new(file, rank).unwrap();
+                let cell = match pos.piece_at(sq) {
+                    Some(p) => format!(
+                        "{}{}",
+                        if p.color() == Color::Black { ' ' } else { 'v' },
+                        PIECE_CHARS[p.piece_kind().array_index()]
+                    ),
+                    None => String::from(" ・"),
+                };
+                if self.is_highlighted(sq) {
+                    write!(f, "{HIGHLIGHT_START}{cell}{HIGHLIGHT_END}")?;
+                } else {
+                    write!(f, "{cell}")?;
+                }
+            }
+            writeln!(f, "|{}", RANK_CHARS[usize::from(rank) - 1])?;
+        }
+        writeln!(f, "{BOARD_FRAME}")?;
+        writeln!(
+            f,
+            "先手の持駒：{}",
+            hand_to_japanese(pos.hand(Color::Black))
+        )?;
+        match pos.side_to_move() {
+            Color::Black => writeln!(f, "先手番")?,
+            Color::White => writeln!(f, "後手番")?,
+        }
+        write!(f, "手数＝{}", pos.ply().saturating_sub(1))?;
+        if let Some((prev, m)) = self.last_move() {
+            let mark = if prev.side_to_move() == Color::Black {
+                '▲'
+            } else {
+                '△'
+            };
+            write!(f, "　{mark}{}　まで", prev.move_to_japanese(m))?;
+        }
+        writeln!(f)?;
+        if self.highlight && pos.in_check() {
+            writeln!(f, "王手")?;
+        }
+        Ok(())
+    }
+    fn fmt_ascii(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = self.pos;
+        for rank in 1..=9 {
+            write!(f, "P{rank}")?;
+            for file in (1..=9).rev() {
+                let sq = Square::new(file, rank).unwrap();
+                let cell = match pos.piece_at(sq) {
+                    Some(p) => format!(
+                        "{}{}",
+                        if p.color() == Color::Black { '+' } else { '-' },
+                        PIECE_CODES[p.piece_kind().array_index()]
+                    ),
+                    None => String::from(" * "),
+                };
+                if self.is_highlighted(sq) {
+                    write!(f, "{HIGHLIGHT_START}{cell}{HIGHLIGHT_END}")?;
+                } else {
+                    write!(f, "{cell}")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        for (c, prefix) in [(Color::Black, "P+"), (Color::White, "P-")] {
+            let hand = pos.hand(c);
+            if hand == Hand::new() {
+                continue;
+            }
+            write!(f, "{prefix}")?;
+            for pk in Hand::all_hand_pieces() {
+                for _ in 0..hand.count(pk).unwrap_or_default() {
+                    write!(f, "00{}", PIECE_CODES[pk.array_index()])?;
+                }
+            }
+            writeln!(f)?;
+        }
+        match pos.side_to_move() {
+            Color::Black => writeln!(f, "+")?,
+            Color::White => writeln!(f, "-")?,
+        }
+        write!(f, "'moves: {}", pos.ply().saturating_sub(1))?;
+        if let Some((prev, m)) = self.last_move() {
+            write!(f, ", last move: {}", format_move(&prev, m))?;
+        }
+        writeln!(f)?;
+        if self.highlight && pos.in_check() {
+            writeln!(f, "'check")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagram<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ascii {
+            self.fmt_ascii(f)
+        } else {
+            self.fmt_kanji(f)
+        }
+    }
+}
+
+impl Position {
+    /// Returns the board diagram, to be configured and then displayed.
+    pub fn diagram(&self) -> Diagram<'_> {
+        Diagram {
+            pos: self,
+            ascii: false,
+            highlight: false,
+        }
+    }
+}
+
+/// Draws the board diagram in kanji. Use [`Position::diagram`] for the other styles.
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.diagram().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::Move;
+
+    #[test]
+    fn kanji() {
+        let mut pos = Position::default();
+        pos.do_move(Move::Normal {
+            from: Square::SQ_7G,
+            to: Square::SQ_7F,
+            promote: false,
+        });
+        assert_eq!(
+            "\
+後手の持駒：なし
+  ９ ８ ７ ６ ５ ４ ３ ２ １
++---------------------------+
+|v香v桂v銀v金v玉v金v銀v桂v香|一
+| ・v飛 ・ ・ ・ ・ ・v角 ・|二
+|v歩v歩v歩v歩v歩v歩v歩v歩v歩|三
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|四
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|五
+| ・ ・ 歩 ・ ・ ・ ・ ・ ・|六
+| 歩 歩 ・ 歩 歩 歩 歩 歩 歩|七
+| ・ 角 ・ ・ ・ ・ ・ 飛 ・|八
+| 香 桂 銀 金 玉 金 銀 桂 香|九
++---------------------------+
+先手の持駒：なし
+後手番
+手数＝1　▲７六歩　まで
+",
+            pos.to_string()
+        );
+    }
+
+    #[test]
+    fn ascii() {
+        let mut pos = position(MATE_IN_ONE);
+        let diagram = pos.diagram().ascii(true).to_string();
+        assert!(diagram.starts_with(
+            "\
+P1 *  *  *  *  *  *  * -KE-OU
+P2 *  *  *  *  *  *  *  * -KY
+P3 *  *  *  *  *  *  * +FU-FU
+"
+        ));
+        assert!(diagram.contains("\nP9 *  *  *  *  * +OU *  *  * \nP+00KI\nP-00FU"));
+        assert!(diagram.contains("00KA00KA00HI00HI\n+\n"));
+        assert!(diagram.ends_with("\n+\n'moves: 0\n"));
+        assert!(diagram.is_ascii());
+
+        pos.do_move(Move::Drop {
+            to: Square::SQ_2B,
+            piece: shogi_core::Piece::B_G,
+        });
+        let diagram = pos.diagram().ascii(true).highlight(true).to_string();
+        assert!(diagram.contains("P1 *  *  *  *  *  *  * -KE\x1b[7m-OU\x1b[0m\n"));
+        assert!(diagram.contains("P2 *  *  *  *  *  *  * \x1b[7m+KI\x1b[0m-KY\n"));
+        assert!(diagram.ends_with("\n-\n'moves: 1, last move: +0022KI\n'check\n"));
+    }
+}
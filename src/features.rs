@@ -0,0 +1,227 @@
+//! Input feature planes of neural networks, in the same layout as [dlshogi](https://github.com/TadaoYamaoka/DeepLearningShogi).
+//!
+//! All planes are 9x9 (81 values), indexed by the square index (`1A`, `1B`, ..., `9I`)
+//! from the viewpoint of the side to move: if white is to move, the board is rotated by 180 degrees
+//! and the colors are swapped, so that "own" pieces always belong to the side to move.
+//!
+//! `features1` consists of [`FEATURES1_NUM`] planes, 31 for own pieces followed by 31 for the opponent's:
+//!
+//! | planes  | description                                                     |
+//! |---------|-----------------------------------------------------------------|
+//! | 0..14   | occupancy of each piece kind, in the order of [`PIECE_KINDS`]   |
+//! | 14..28  | squares attacked by each piece kind                             |
+//! | 28..31  | squares attacked by at least 1, 2 and 3 pieces                  |
+//!
+//! `features2` consists of [`FEATURES2_NUM`] planes filled with either 0 or 1:
+//! 28 for own pieces in hand and 28 for the opponent's, where the `n`-th plane of a piece kind
+//! is filled if there are more than `n` pieces of it (see [`MAX_PIECES_IN_HAND`]),
+//! followed by a plane filled if the side to move is in check.
+use crate::tables::ATTACK_TABLE;
+use crate::Position;
+use shogi_core::{Color, Piece, PieceKind, Square};
+
+/// Piece kinds in the order of the planes.
+pub const PIECE_KINDS: [PieceKind; 14] = [
+    PieceKind::Pawn,
+    PieceKind::Lance,
+    PieceKind::Knight,
+    PieceKind::Silver,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+    PieceKind::Gold,
+    PieceKind::King,
+    PieceKind::ProPawn,
+    PieceKind::ProLance,
+    PieceKind::ProKnight,
+    PieceKind::ProSilver,
+    PieceKind::ProBishop,
+    PieceKind::ProRook,
+];
+
+/// Piece kinds in hand in the order of the planes, with the maximum numbers to be represented.
+pub const MAX_PIECES_IN_HAND: [(PieceKind, u8); 7] = [
+    (PieceKind::Pawn, 8),
+    (PieceKind::Lance, 4),
+    (PieceKind::Knight, 4),
+    (PieceKind::Silver, 4),
+    (PieceKind::Gold, 4),
+    (PieceKind::Bishop, 2),
+    (PieceKind::Rook, 2),
+];
+
+const MAX_ATTACK_NUM: usize = 3;
+const PIECES_IN_HAND_NUM: usize = 28;
+
+pub const FEATURES1_NUM: usize = 2 * (2 * PIECE_KINDS.len() + MAX_ATTACK_NUM);
+pub const FEATURES2_NUM: usize = 2 * PIECES_IN_HAND_NUM + 1;
+
+/// Fills the feature planes of the position.
+/// `T` is typically `f32` or `u8`, and every value is set to either 0 or 1.
+///
+/// # Panics
+///
+/// Panics if `features1` is shorter than `FEATURES1_NUM * 81` or `features2` is shorter than `FEATURES2_NUM * 81`.
+pub fn make_input_features<T: Copy + From<u8>>(
+    pos: &Position,
+    features1: &mut [T],
+    features2: &mut [T],
+) {
+    let features1 = &mut features1[..FEATURES1_NUM * Square::NUM];
+    let features2 = &mut features2[..FEATURES2_NUM * Square::NUM];
+    let one = T::from(1);
+    features1.fill(T::from(0));
+    features2.fill(T::from(0));
+
+    let turn = pos.side_to_move();
+    let relative_index = |sq: Square| match turn {
+        Color::Black => sq.array_index(),
+        Color::White => Square::NUM - 1 - sq.array_index(),
+    };
+    let occ = pos.occupied_bitboard();
+    for c in Color::all() {
+        let planes1 = if c == turn {
+            &mut features1[..FEATURES1_NUM / 2 * Square::NUM]
+        } else {
+            &mut features1[FEATURES1_NUM / 2 * Square::NUM..]
+        };
+        let mut attack_nums = [0; Square::NUM];
+        for (i, &pk) in PIECE_KINDS.iter().enumerate() {
+            for sq in pos.piece_bitboard(Piece::new(pk, c)) {
+                planes1[i * Square::NUM + relative_index(sq)] = one;
+                for to in ATTACK_TABLE.attack(pk, sq, c, &occ) {
+                    let index = relative_index(to);
+                    planes1[(PIECE_KINDS.len() + i) * Square::NUM + index] = one;
+                    attack_nums[index] += 1;
+                }
+            }
+        }
+        for (index, &num) in attack_nums.iter().enumerate() {
+            for n in 0..MAX_ATTACK_NUM.min(num) {
+                planes1[(2 * PIECE_KINDS.len() + n) * Square::NUM + index] = one;
+            }
+        }
+
+        let planes2 = if c == turn {
+            &mut features2[..PIECES_IN_HAND_NUM * Square::NUM]
+        } else {
+            &mut features2[PIECES_IN_HAND_NUM * Square::NUM..2 * PIECES_IN_HAND_NUM * Square::NUM]
+        };
+        let mut offset = 0;
+        for (pk, max) in MAX_PIECES_IN_HAND {
+            let num = pos.hand(c).count(pk).unwrap_or_default().min(max);
+            planes2[offset * Square::NUM..(offset + usize::from(num)) * Square::NUM].fill(one);
+            offset += usize::from(max);
+        }
+    }
+    if pos.in_check() {
+        features2[(FEATURES2_NUM - 1) * Square::NUM..].fill(one);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::PartialPosition;
+    use shogi_usi_parser::FromUsi;
+
+    fn features<T: Copy + From<u8>>(pos: &Position) -> (Vec<T>, Vec<T>) {
+        let mut features1 = vec![T::from(0); FEATURES1_NUM * Square::NUM];
+        let mut features2 = vec![T::from(0); FEATURES2_NUM * Square::NUM];
+        make_input_features(pos, &mut features1, &mut features2);
+        (features1, features2)
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(62, FEATURES1_NUM);
+        assert_eq!(57, FEATURES2_NUM);
+        assert_eq!(
+            PIECES_IN_HAND_NUM,
+            MAX_PIECES_IN_HAND
+                .iter()
+                .map(|&(_, max)| usize::from(max))
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn startpos() {
+        let (features1, features2) = features::<u8>(&Position::default());
+        // own pawns
+        for sq in Square::all() {
+            let expected = u8::from(sq.rank() == 7);
+            assert_eq!(expected, features1[sq.array_index()], "{sq:?}");
+        }
+        // own rook and its attacks
+        assert_eq!(1, features1[5 * Square::NUM + Square::SQ_2H.array_index()]);
+        assert_eq!(
+            9,
+            features1[19 * Square::NUM..20 * Square::NUM]
+                .iter()
+                .sum::<u8>()
+        );
+        // 7G is attacked by the bishop and the knight, but 2F is attacked by the pawn only
+        assert_eq!(1, features1[29 * Square::NUM + Square::SQ_7G.array_index()]);
+        assert_eq!(0, features1[29 * Square::NUM + Square::SQ_2F.array_index()]);
+        // opponent's king
+        assert_eq!(
+            1,
+            features1[(31 + 7) * Square::NUM + Square::SQ_5A.array_index()]
+        );
+        assert!(features2.iter().all(|&v| v == 0));
+
+        // the same features with white to move
+        let pos = Position::new(
+            PartialPosition::from_usi(
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            )
+            .expect("failed to parse"),
+        );
+        assert_eq!((features1, features2), features::<u8>(&pos));
+    }
+
+    #[test]
+    fn hands_and_check() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  *  *  *  *  *  * +KY
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  *  * +OU *  *  *
+        // P+00HI00KI00FU00FU00FU00FU00FU00FU00FU00FU00FU00FU
+        // P-00AL
+        // -
+        let pos = Position::new(
+            PartialPosition::from_usi("sfen 8k/9/8L/9/9/9/9/9/5K3 w RG10Pr2b3g4s4n3l8p 1")
+                .expect("failed to parse"),
+        );
+        let (features1, features2) = features::<f32>(&pos);
+        // own king on 1A is on 9I after rotation
+        assert_eq!(
+            1.0,
+            features1[7 * Square::NUM + Square::SQ_9I.array_index()]
+        );
+        // opponent's lance on 1C
+        assert_eq!(
+            1.0,
+            features1[(31 + 1) * Square::NUM + Square::SQ_9G.array_index()]
+        );
+        let plane = |i: usize| &features2[i * Square::NUM..(i + 1) * Square::NUM];
+        let filled = |i: usize| plane(i).iter().all(|&v| v == 1.0);
+        let empty = |i: usize| plane(i).iter().all(|&v| v == 0.0);
+        // own pieces: 8 pawns, 3 lances, 4 knights, 4 silvers, 3 golds, 2 bishops, 1 rook
+        assert!((0..11).all(filled) && empty(11));
+        assert!((12..23).all(filled) && empty(23));
+        assert!((24..27).all(filled) && empty(27));
+        // opponent's pieces: 10 pawns represented by 8 planes, 1 gold, 1 rook
+        assert!((28..36).all(filled));
+        assert!((36..48).all(empty));
+        assert!(filled(48) && (49..54).all(empty));
+        assert!(filled(54) && empty(55));
+        // in check
+        assert!(filled(56));
+    }
+}
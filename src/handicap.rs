@@ -0,0 +1,201 @@
+//! Standard handicaps (駒落ち), where white gives the pieces and moves first.
+use crate::Position;
+use shogi_core::{Color, Hand, PartialPosition, Square};
+
+/// Standard handicap, named by the pieces removed from white.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Handicap {
+    /// No handicap (平手), where black moves first
+    Even,
+    /// The lance on 1a (香落ち)
+    Lance,
+    Bishop,
+    Rook,
+    RookLance,
+    /// The rook and the bishop (二枚落ち)
+    TwoPieces,
+    /// Also the lances (四枚落ち)
+    FourPieces,
+    /// Also the knights (六枚落ち)
+    SixPieces,
+    /// Also the silvers (八枚落ち)
+    EightPieces,
+    /// Also the golds (十枚落ち)
+    TenPieces,
+}
+
+impl Handicap {
+    pub const ALL: [Handicap; 10] = [
+        Handicap::Even,
+        Handicap::Lance,
+        Handicap::Bishop,
+        Handicap::Rook,
+        Handicap::RookLance,
+        Handicap::TwoPieces,
+        Handicap::FourPieces,
+        Handicap::SixPieces,
+        Handicap::EightPieces,
+        Handicap::TenPieces,
+    ];
+
+    /// Returns the squares of the removed pieces.
+    pub fn removed_squares(self) -> &'static [Square] {
+        const TEN_PIECES: [Square; 10] = [
+            Square::SQ_8B,
+            Square::SQ_2B,
+            Square::SQ_1A,
+            Square::SQ_9A,
+            Square::SQ_2A,
+            Square::SQ_8A,
+            Square::SQ_3A,
+            Square::SQ_7A,
+            Square::SQ_4A,
+            Square::SQ_6A,
+        ];
+        match self {
+            Handicap::Even => &[],
+            Handicap::Lance => &[Square::SQ_1A],
+            Handicap::Bishop => &[Square::SQ_2B],
+            Handicap::Rook => &[Square::SQ_8B],
+            Handicap::RookLance => &[Square::SQ_8B, Square::SQ_1A],
+            Handicap::TwoPieces => &TEN_PIECES[..2],
+            Handicap::FourPieces => &TEN_PIECES[..4],
+            Handicap::SixPieces => &TEN_PIECES[..6],
+            Handicap::EightPieces => &TEN_PIECES[..8],
+            Handicap::TenPieces => &TEN_PIECES,
+        }
+    }
+    /// Returns the name used in `手合割` of KIF, such as `飛香落ち`.
+    pub fn japanese(self) -> &'static str {
+        match self {
+            Handicap::Even => "平手",
+            Handicap::Lance => "香落ち",
+            Handicap::Bishop => "角落ち",
+            Handicap::Rook => "飛車落ち",
+            Handicap::RookLance => "飛香落ち",
+            Handicap::TwoPieces => "二枚落ち",
+            Handicap::FourPieces => "四枚落ち",
+            Handicap::SixPieces => "六枚落ち",
+            Handicap::EightPieces => "八枚落ち",
+            Handicap::TenPieces => "十枚落ち",
+        }
+    }
+    pub fn from_japanese(s: &str) -> Option<Handicap> {
+        Handicap::ALL.into_iter().find(|h| h.japanese() == s)
+    }
+    /// Returns the starting position, with white to move unless [`Handicap::Even`].
+    pub fn to_partial_position(self) -> PartialPosition {
+        let mut partial = PartialPosition::startpos();
+        for &sq in self.removed_squares() {
+            partial.piece_set(sq, None);
+        }
+        if self != Handicap::Even {
+            partial.side_to_move_set(Color::White);
+        }
+        partial
+    }
+    /// Returns the handicap whose starting position has the same board, pieces in hand and side
+    /// to move as the position, ignoring the ply.
+    pub fn detect(partial: &PartialPosition) -> Option<Handicap> {
+        if Color::all()
+            .iter()
+            .any(|&c| partial.hand_of_a_player(c) != Hand::new())
+        {
+            return None;
+        }
+        Handicap::ALL.into_iter().find(|h| {
+            let start = h.to_partial_position();
+            start.side_to_move() == partial.side_to_move()
+                && Square::all().all(|sq| start.piece_at(sq) == partial.piece_at(sq))
+        })
+    }
+}
+
+impl Position {
+    /// Returns the starting position of the handicap.
+    pub fn with_handicap(handicap: Handicap) -> Position {
+        Position::new(handicap.to_partial_position())
+    }
+    /// Returns the handicap whose starting position is the current one, if any.
+    pub fn handicap(&self) -> Option<Handicap> {
+        Handicap::detect(&self.to_partial_position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_usi_parser::FromUsi;
+
+    #[test]
+    fn positions() {
+        for (handicap, sfen) in [
+            (
+                Handicap::Even,
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            ),
+            (
+                Handicap::Lance,
+                "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::Bishop,
+                "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::Rook,
+                "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::RookLance,
+                "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::TwoPieces,
+                "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::FourPieces,
+                "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::SixPieces,
+                "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::EightPieces,
+                "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::TenPieces,
+                "4k4/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+        ] {
+            assert_eq!(sfen, handicap.to_partial_position().to_sfen_owned());
+            let pos = Position::with_handicap(handicap);
+            assert_eq!(Some(handicap), pos.handicap());
+            assert_eq!(Ok(()), pos.validate());
+            assert_eq!(Some(handicap), Handicap::from_japanese(handicap.japanese()));
+        }
+        assert_eq!(Position::default(), Position::with_handicap(Handicap::Even));
+    }
+
+    #[test]
+    fn detect() {
+        let mut pos = Position::with_handicap(Handicap::Bishop);
+        assert_eq!(Some(Handicap::Bishop), pos.handicap());
+        pos.do_move(shogi_core::Move::from_usi("5a4b").expect("failed to parse"));
+        assert_eq!(None, pos.handicap());
+
+        // the side to move and the pieces in hand are compared
+        let mut partial = Handicap::Rook.to_partial_position();
+        partial.side_to_move_set(Color::Black);
+        assert_eq!(None, Handicap::detect(&partial));
+        let mut partial = Handicap::Rook.to_partial_position();
+        *partial.hand_of_a_player_mut(Color::Black) = Hand::new()
+            .added(shogi_core::PieceKind::Rook)
+            .expect("failed to add");
+        assert_eq!(None, Handicap::detect(&partial));
+        assert_eq!(None, Handicap::from_japanese("右香落ち"));
+    }
+}
@@ -0,0 +1,501 @@
+//! [Apery](https://github.com/HiraokaTakuya/apery)'s `HuffmanCodedPos` (HCP) encoding of a position,
+//! and the `hcpe` / `hcpe3` training data formats used by Apery and dlshogi.
+use crate::bitstream::{BitReader, BitWriter};
+use crate::Position;
+use shogi_core::{Color, Move, PartialPosition, Piece, PieceKind, Square};
+use std::io::{self, Read, Write};
+
+/// Huffman codes `(code, bits)` of the pieces on the board.
+/// The first entry stands for an empty square, followed by the pieces of each color
+/// in the order of [`PieceKind`]. Kings have no codes since their squares are stored separately.
+#[rustfmt::skip]
+const BOARD_CODES: [(u32, usize); 1 + 2 * PieceKind::NUM] = [
+    (0b0, 1), // Empty
+    // Black
+    (0b1, 4), (0b11, 6), (0b111, 6), (0b1011, 6), (0b1111, 6), (0b11111, 8), (0b111111, 8), (0, 0),
+    (0b1001, 4), (0b100011, 6), (0b100111, 6), (0b101011, 6), (0b10011111, 8), (0b10111111, 8),
+    // White
+    (0b101, 4), (0b10011, 6), (0b10111, 6), (0b11011, 6), (0b101111, 6), (0b1011111, 8), (0b1111111, 8), (0, 0),
+    (0b1101, 4), (0b110011, 6), (0b110111, 6), (0b111011, 6), (0b11011111, 8), (0b11111111, 8),
+];
+
+/// Huffman codes of the pieces in hand, for each color in the order of [`HAND_PIECES`].
+#[rustfmt::skip]
+const HAND_CODES: [(u32, usize); 2 * 7] = [
+    // Black
+    (0b0, 3), (0b1, 5), (0b11, 5), (0b101, 5), (0b111, 5), (0b11111, 7), (0b111111, 7),
+    // White
+    (0b100, 3), (0b10001, 5), (0b10011, 5), (0b10101, 5), (0b10111, 5), (0b1011111, 7), (0b1111111, 7),
+];
+
+/// Piece kinds in hand, in the order of Apery's `HandPiece`.
+const HAND_PIECES: [PieceKind; 7] = [
+    PieceKind::Pawn,
+    PieceKind::Lance,
+    PieceKind::Knight,
+    PieceKind::Silver,
+    PieceKind::Gold,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+];
+
+/// Piece kinds in the order of Apery's `PieceType`, used for drops of 16-bit moves.
+const PIECE_TYPES: [PieceKind; 7] = [
+    PieceKind::Pawn,
+    PieceKind::Lance,
+    PieceKind::Knight,
+    PieceKind::Silver,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+    PieceKind::Gold,
+];
+
+/// A position encoded in 256 bits. It requires both kings on the board and all the other 38 pieces on the board or in hands.
+///
+/// Note that the ply is not a part of the encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HuffmanCodedPos(pub [u8; 32]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    Draw = 0,
+    BlackWin = 1,
+    WhiteWin = 2,
+}
+
+impl GameResult {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(GameResult::Draw),
+            1 => Some(GameResult::BlackWin),
+            2 => Some(GameResult::WhiteWin),
+            _ => None,
+        }
+    }
+}
+
+/// A 38-byte record of a `hcpe` file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HuffmanCodedPosAndEval {
+    pub hcp: HuffmanCodedPos,
+    /// Evaluation value from the side to move
+    pub eval: i16,
+    /// Best move in Apery's 16-bit move format, see [`encode_move`]
+    pub best_move16: u16,
+    pub game_result: GameResult,
+}
+
+impl HuffmanCodedPosAndEval {
+    pub const SIZE: usize = 38;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let mut hcp = [0; 32];
+        hcp.copy_from_slice(&bytes[..32]);
+        Some(Self {
+            hcp: HuffmanCodedPos(hcp),
+            eval: i16::from_le_bytes([bytes[32], bytes[33]]),
+            best_move16: u16::from_le_bytes([bytes[34], bytes[35]]),
+            game_result: GameResult::from_u8(bytes[36])?,
+        })
+    }
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..32].copy_from_slice(&self.hcp.0);
+        bytes[32..34].copy_from_slice(&self.eval.to_le_bytes());
+        bytes[34..36].copy_from_slice(&self.best_move16.to_le_bytes());
+        bytes[36] = self.game_result as u8;
+        bytes
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+/// Reads [`HuffmanCodedPosAndEval`]s from a `hcpe` file until its end.
+pub struct HcpeReader<R> {
+    reader: R,
+}
+
+impl<R: Read> HcpeReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: Read> Iterator for HcpeReader<R> {
+    type Item = io::Result<HuffmanCodedPosAndEval>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0; HuffmanCodedPosAndEval::SIZE];
+        if let Err(e) = self.reader.read_exact(&mut buf) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => None,
+                _ => Some(Err(e)),
+            };
+        }
+        Some(HuffmanCodedPosAndEval::from_bytes(&buf).ok_or_else(invalid_data))
+    }
+}
+
+/// Visit count of a candidate move in a `hcpe3` record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MoveVisits {
+    pub move16: u16,
+    pub visit_num: u16,
+}
+
+/// A move of a `hcpe3` record, with the visit counts of the candidate moves.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MoveInfo {
+    pub selected_move16: u16,
+    pub eval: i16,
+    pub candidates: Vec<MoveVisits>,
+}
+
+/// A game of a `hcpe3` file: the initial position followed by the moves.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Hcpe3Game {
+    pub hcp: HuffmanCodedPos,
+    /// Bits 0-1 for [`GameResult`], bit 2 for sennichite, bit 3 for nyugyoku and bit 4 for reaching the max moves
+    pub result: u8,
+    /// 0 for self-play, 1 if black is the opponent and 2 if white is the opponent
+    pub opponent: u8,
+    pub moves: Vec<MoveInfo>,
+}
+
+impl Hcpe3Game {
+    const HEADER_SIZE: usize = 36;
+
+    pub fn game_result(&self) -> Option<GameResult> {
+        GameResult::from_u8(self.result & 0b11)
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let move_num = u16::try_from(self.moves.len()).map_err(|_| invalid_data())?;
+        writer.write_all(&self.hcp.0)?;
+        writer.write_all(&move_num.to_le_bytes())?;
+        writer.write_all(&[self.result, self.opponent])?;
+        for info in &self.moves {
+            let candidate_num = u16::try_from(info.candidates.len()).map_err(|_| invalid_data())?;
+            writer.write_all(&info.selected_move16.to_le_bytes())?;
+            writer.write_all(&info.eval.to_le_bytes())?;
+            writer.write_all(&candidate_num.to_le_bytes())?;
+            for mv in &info.candidates {
+                writer.write_all(&mv.move16.to_le_bytes())?;
+                writer.write_all(&mv.visit_num.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads [`Hcpe3Game`]s from a `hcpe3` file until its end.
+pub struct Hcpe3Reader<R> {
+    reader: R,
+}
+
+impl<R: Read> Hcpe3Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+    fn read_game(&mut self) -> io::Result<Option<Hcpe3Game>> {
+        let mut header = [0; Hcpe3Game::HEADER_SIZE];
+        if let Err(e) = self.reader.read_exact(&mut header) {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e),
+            };
+        }
+        let mut hcp = [0; 32];
+        hcp.copy_from_slice(&header[..32]);
+        let move_num = u16::from_le_bytes([header[32], header[33]]);
+        let mut moves = Vec::with_capacity(usize::from(move_num));
+        for _ in 0..move_num {
+            let mut buf = [0; 6];
+            self.reader.read_exact(&mut buf)?;
+            let candidate_num = u16::from_le_bytes([buf[4], buf[5]]);
+            let mut candidates = Vec::with_capacity(usize::from(candidate_num));
+            for _ in 0..candidate_num {
+                let mut buf = [0; 4];
+                self.reader.read_exact(&mut buf)?;
+                candidates.push(MoveVisits {
+                    move16: u16::from_le_bytes([buf[0], buf[1]]),
+                    visit_num: u16::from_le_bytes([buf[2], buf[3]]),
+                });
+            }
+            moves.push(MoveInfo {
+                selected_move16: u16::from_le_bytes([buf[0], buf[1]]),
+                eval: i16::from_le_bytes([buf[2], buf[3]]),
+                candidates,
+            });
+        }
+        Ok(Some(Hcpe3Game {
+            hcp: HuffmanCodedPos(hcp),
+            result: header[34],
+            opponent: header[35],
+            moves,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for Hcpe3Reader<R> {
+    type Item = io::Result<Hcpe3Game>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_game().transpose()
+    }
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::from(io::ErrorKind::InvalidData)
+}
+
+/// Encodes a move into Apery's 16-bit format:
+/// destination in bits 0-6, source in bits 7-13 and promotion flag in bit 14.
+/// The source of a drop is `81 + PieceType - 1`.
+pub fn encode_move(m: Move) -> u16 {
+    match m {
+        Move::Normal { from, to, promote } => {
+            u16::from(to.index() - 1)
+                | (u16::from(from.index() - 1) << 7)
+                | (u16::from(promote) << 14)
+        }
+        Move::Drop { to, piece } => {
+            let pt = PIECE_TYPES
+                .iter()
+                .position(|&pk| pk == piece.piece_kind())
+                .unwrap_or_default();
+            u16::from(to.index() - 1) | (((Square::NUM + pt) as u16) << 7)
+        }
+    }
+}
+
+/// Decodes a move from Apery's 16-bit format. The color is needed to determine the dropped piece.
+pub fn decode_move(m16: u16, c: Color) -> Option<Move> {
+    let to = Square::from_u8((m16 & 0x7f) as u8 + 1)?;
+    let from = usize::from((m16 >> 7) & 0x7f);
+    if from >= Square::NUM {
+        let pk = *PIECE_TYPES.get(from - Square::NUM)?;
+        return Some(Move::Drop {
+            to,
+            piece: Piece::new(pk, c),
+        });
+    }
+    let from = Square::from_u8(from as u8 + 1)?;
+    if from == to {
+        return None;
+    }
+    Some(Move::Normal {
+        from,
+        to,
+        promote: m16 & (1 << 14) != 0,
+    })
+}
+
+impl Position {
+    /// Encodes the position into a [`HuffmanCodedPos`].
+    /// Returns `None` if the pieces can't be packed into 256 bits, e.g. some of them are missing.
+    pub fn to_hcp(&self) -> Option<HuffmanCodedPos> {
+        let mut writer = BitWriter::new();
+        writer.write_bit(self.side_to_move() == Color::White);
+        for c in Color::all() {
+            writer.write_bits(self.king_position(c)?.array_index() as u32, 7);
+        }
+        for sq in Square::all() {
+            let index = match self.piece_at(sq) {
+                Some(p) if p.piece_kind() == PieceKind::King => continue,
+                Some(p) => {
+                    1 + p.color().array_index() * PieceKind::NUM + p.piece_kind().array_index()
+                }
+                None => 0,
+            };
+            let (code, bits) = BOARD_CODES[index];
+            writer.write_bits(code, bits);
+        }
+        for c in Color::all() {
+            for (i, &pk) in HAND_PIECES.iter().enumerate() {
+                for _ in 0..self.hand(c).count(pk).unwrap_or_default() {
+                    let (code, bits) = HAND_CODES[c.array_index() * HAND_PIECES.len() + i];
+                    writer.write_bits(code, bits);
+                }
+            }
+        }
+        writer.finish().map(HuffmanCodedPos)
+    }
+    /// Decodes a [`HuffmanCodedPos`] with the given ply.
+    /// Returns `None` if the data is not a valid encoding.
+    pub fn from_hcp(hcp: &HuffmanCodedPos, ply: u16) -> Option<Position> {
+        let mut partial = PartialPosition::empty();
+        let mut reader = BitReader::new(&hcp.0);
+        partial.side_to_move_set([Color::Black, Color::White][usize::from(reader.read_bit()?)]);
+        if !partial.ply_set(ply) {
+            return None;
+        }
+        let mut kings = [Square::SQ_1A; Color::NUM];
+        for c in Color::all() {
+            let sq = Square::from_u8(reader.read_bits(7)? as u8 + 1)?;
+            partial.piece_set(sq, Some(Piece::new(PieceKind::King, c)));
+            kings[c.array_index()] = sq;
+        }
+        if kings[0] == kings[1] {
+            return None;
+        }
+        for sq in Square::all() {
+            if kings.contains(&sq) {
+                continue;
+            }
+            let index = match reader.read_huffman(&BOARD_CODES)? {
+                0 => continue,
+                i => i - 1,
+            };
+            let c = [Color::Black, Color::White][index / PieceKind::NUM];
+            let pk = PieceKind::all()[index % PieceKind::NUM];
+            partial.piece_set(sq, Some(Piece::new(pk, c)));
+        }
+        while !reader.is_end() {
+            let index = reader.read_huffman(&HAND_CODES)?;
+            let c = [Color::Black, Color::White][index / HAND_PIECES.len()];
+            let hand = partial.hand_of_a_player_mut(c);
+            *hand = hand.added(HAND_PIECES[index % HAND_PIECES.len()])?;
+        }
+        Some(Position::new(partial))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_usi_parser::FromUsi;
+
+    #[test]
+    fn startpos() {
+        let pos = Position::default();
+        let hcp = pos.to_hcp().expect("failed to encode");
+        let decoded = Position::from_hcp(&hcp, 1).expect("failed to decode");
+        assert!(Square::all().all(|sq| pos.piece_at(sq) == decoded.piece_at(sq)));
+        assert_eq!(pos.key(), decoded.key());
+        // turn bit and the black king on 5I (index 44)
+        assert_eq!(0b0101_1000, hcp.0[0]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for sfen in [
+            "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+            "sfen 4k3K/7+p1/8+p/1p5+p1/p1p5+p/1+p1p3+p1/+p3p3+p/1+p3p1+p1/+p5+p2 w 2R2B4G4S4N4L 1",
+            "sfen l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            let hcp = pos.to_hcp().expect("failed to encode");
+            let decoded = Position::from_hcp(&hcp, 1).expect("failed to decode");
+            assert_eq!(pos.key(), decoded.key(), "{sfen}");
+            assert_eq!(pos.side_to_move(), decoded.side_to_move(), "{sfen}");
+        }
+        for sfen in [
+            "sfen 4k4/9/9/9/9/9/9/9/4K4 b 2R2B4G4S4N4L17P 1",
+            "sfen 9/9/9/9/9/9/9/9/9 b 2R2B4G4S4N4L18P 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            assert_eq!(None, pos.to_hcp(), "{sfen}");
+        }
+    }
+
+    #[test]
+    fn moves() {
+        let pos = Position::new(
+            PartialPosition::from_usi("sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1")
+                .expect("failed to parse"),
+        );
+        for m in pos.legal_moves() {
+            assert_eq!(Some(m), decode_move(encode_move(m), Color::Black));
+        }
+        let m = Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::B_G,
+        };
+        assert_eq!(((81 + 7 - 1) << 7) | 40, encode_move(m));
+    }
+
+    #[test]
+    fn hcpe() {
+        let pos = Position::default();
+        let record = HuffmanCodedPosAndEval {
+            hcp: pos.to_hcp().expect("failed to encode"),
+            eval: 42,
+            best_move16: encode_move(Move::Normal {
+                from: Square::SQ_2G,
+                to: Square::SQ_2F,
+                promote: false,
+            }),
+            game_result: GameResult::WhiteWin,
+        };
+        let mut buf = Vec::new();
+        record.write_to(&mut buf).expect("failed to write");
+        record.write_to(&mut buf).expect("failed to write");
+        assert_eq!(2 * HuffmanCodedPosAndEval::SIZE, buf.len());
+        let records = HcpeReader::new(buf.as_slice())
+            .collect::<io::Result<Vec<_>>>()
+            .expect("failed to read");
+        assert_eq!(vec![record, record], records);
+
+        buf[36] = 3;
+        assert!(HcpeReader::new(buf.as_slice()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn hcpe3() {
+        let mut pos = Position::default();
+        let hcp = pos.to_hcp().expect("failed to encode");
+        let mut moves = Vec::new();
+        for _ in 0..4 {
+            let legal_moves = pos.legal_moves();
+            moves.push(MoveInfo {
+                selected_move16: encode_move(legal_moves[0]),
+                eval: -(moves.len() as i16),
+                candidates: legal_moves
+                    .iter()
+                    .take(3)
+                    .enumerate()
+                    .map(|(i, &m)| MoveVisits {
+                        move16: encode_move(m),
+                        visit_num: 10 - i as u16,
+                    })
+                    .collect(),
+            });
+            pos.do_move(legal_moves[0]);
+        }
+        let games = vec![
+            Hcpe3Game {
+                hcp,
+                result: GameResult::BlackWin as u8 | 0b1000,
+                opponent: 0,
+                moves,
+            },
+            Hcpe3Game {
+                hcp,
+                result: GameResult::Draw as u8,
+                opponent: 2,
+                moves: Vec::new(),
+            },
+        ];
+        let mut buf = Vec::new();
+        for game in &games {
+            game.write_to(&mut buf).expect("failed to write");
+        }
+        assert_eq!(2 * 36 + 4 * (6 + 3 * 4), buf.len());
+        let decoded = Hcpe3Reader::new(buf.as_slice())
+            .collect::<io::Result<Vec<_>>>()
+            .expect("failed to read");
+        assert_eq!(games, decoded);
+        assert_eq!(Some(GameResult::BlackWin), decoded[0].game_result());
+
+        // replay the moves from the initial position
+        let mut pos = Position::from_hcp(&decoded[0].hcp, 1).expect("failed to decode");
+        for info in &decoded[0].moves {
+            let m = decode_move(info.selected_move16, pos.side_to_move()).expect("invalid move");
+            assert!(pos.legal_moves().contains(&m));
+            pos.do_move(m);
+        }
+        // truncated data
+        assert!(Hcpe3Reader::new(&buf[..40]).next().unwrap().is_err());
+    }
+}
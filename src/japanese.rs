@@ -0,0 +1,299 @@
+//! Japanese move notation and its disambiguation.
+use crate::Position;
+use shogi_core::{Move, Piece, PieceKind, Square};
+
+pub(crate) const FILE_CHARS: [char; 9] = ['１', '２', '３', '４', '５', '６', '７', '８', '９'];
+pub(crate) const RANK_CHARS: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+/// Names of the piece kinds in moves, indexed by `PieceKind::array_index`.
+pub(crate) const PIECE_NAMES: [&str; PieceKind::NUM] = [
+    "歩", "香", "桂", "銀", "金", "角", "飛", "玉", "と", "成香", "成桂", "成銀", "馬", "龍",
+];
+/// Names of the piece kinds in board diagrams, indexed by `PieceKind::array_index`.
+pub(crate) const PIECE_CHARS: [char; PieceKind::NUM] = [
+    '歩', '香', '桂', '銀', '金', '角', '飛', '玉', 'と', '杏', '圭', '全', '馬', '龍',
+];
+
+/// Designations of the moved piece, in the order of preference.
+#[rustfmt::skip]
+const DESIGNATIONS: [&str; 12] = [
+    "上", "引", "寄",
+    "直", "右", "左",
+    "右上", "右引", "右寄", "左上", "左引", "左寄",
+];
+
+/// Parses the piece kind at the beginning of `s`, and returns it with the rest.
+pub(crate) fn parse_piece_kind(s: &str) -> Option<(PieceKind, &str)> {
+    // two-character names first, so that "成香" is not taken as a promotion
+    for (i, name) in PIECE_NAMES.iter().enumerate().rev() {
+        if let Some(rest) = s.strip_prefix(name) {
+            return Some((PieceKind::all()[i], rest));
+        }
+    }
+    let mut chars = s.chars();
+    let pk = parse_piece_char(chars.next()?)?;
+    Some((pk, chars.as_str()))
+}
+
+pub(crate) fn parse_piece_char(c: char) -> Option<PieceKind> {
+    match c {
+        '王' => Some(PieceKind::King),
+        '竜' => Some(PieceKind::ProRook),
+        _ => PIECE_CHARS
+            .iter()
+            .position(|&pc| pc == c)
+            .map(|i| PieceKind::all()[i]),
+    }
+}
+
+/// Parses the destination square at the beginning of `s`, such as `７六`, and returns it with the rest.
+pub(crate) fn parse_square(s: &str) -> Option<(Square, &str)> {
+    let mut chars = s.chars();
+    let file = parse_digit(chars.next()?)?;
+    let rank = parse_digit(chars.next()?)?;
+    Some((Square::new(file, rank)?, chars.as_str()))
+}
+
+fn parse_digit(c: char) -> Option<u8> {
+    FILE_CHARS
+        .iter()
+        .position(|&fc| fc == c)
+        .or_else(|| RANK_CHARS.iter().position(|&rc| rc == c))
+        .map(|i| i as u8 + 1)
+        .or_else(|| c.to_digit(10).map(|d| d as u8))
+}
+
+/// Returns whether the move from `from` to `to` fits the designation character,
+/// among the pieces on `candidates` which can move to the same square.
+fn designates(c: char, p: Piece, from: Square, to: Square, candidates: &[Square]) -> bool {
+    let color = p.color();
+    let file = from.relative_file(color);
+    let (from_rank, to_rank) = (from.relative_rank(color), to.relative_rank(color));
+    match c {
+        '右' => candidates.iter().all(|sq| sq.relative_file(color) >= file),
+        '左' => candidates.iter().all(|sq| sq.relative_file(color) <= file),
+        // dragons and horses are designated by 右 and 左 instead
+        '直' => {
+            from.file() == to.file()
+                && to_rank < from_rank
+                && !matches!(p.piece_kind(), PieceKind::ProBishop | PieceKind::ProRook)
+        }
+        '上' | '行' => to_rank < from_rank,
+        '引' => to_rank > from_rank,
+        '寄' => to_rank == from_rank,
+        _ => false,
+    }
+}
+
+fn is_promotable(p: Piece, from: Square, to: Square) -> bool {
+    p.promote().is_some()
+        && (from.relative_rank(p.color()) <= 3 || to.relative_rank(p.color()) <= 3)
+}
+
+impl Position {
+    /// Returns the squares of the pieces which can legally move to `to`.
+    fn move_candidates(&self, p: Piece, to: Square) -> Vec<Square> {
+        let mut candidates = self
+            .legal_moves()
+            .into_iter()
+            .filter_map(|m| match m {
+                Move::Normal { from, to: t, .. } if t == to && self.piece_at(from) == Some(p) => {
+                    Some(from)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|sq| sq.index());
+        candidates.dedup();
+        candidates
+    }
+    /// Returns the move in the Japanese notation, such as `７六歩`, `同　銀`, `５二金直` or `２三銀不成`.
+    /// The relative position (`右`, `左`, `直`), the movement (`上`, `引`, `寄`) and `打` are added
+    /// only if they are needed to tell which piece moves.
+    pub fn move_to_japanese(&self, m: Move) -> String {
+        let to = m.to();
+        let mut s = if self.last_move().map(|lm| lm.to()) == Some(to) {
+            String::from("同　")
+        } else {
+            format!(
+                "{}{}",
+                FILE_CHARS[usize::from(to.file()) - 1],
+                RANK_CHARS[usize::from(to.rank()) - 1]
+            )
+        };
+        match m {
+            Move::Normal { from, promote, .. } => {
+                let Some(p) = self.piece_at(from) else {
+                    return s;
+                };
+                s.push_str(PIECE_NAMES[p.piece_kind().array_index()]);
+                let candidates = self.move_candidates(p, to);
+                if candidates.len() > 1 {
+                    let designation = DESIGNATIONS.iter().find(|designation| {
+                        let fits = |from| {
+                            designation
+                                .chars()
+                                .all(|c| designates(c, p, from, to, &candidates))
+                        };
+                        fits(from) && candidates.iter().filter(|&&sq| fits(sq)).count() == 1
+                    });
+                    if let Some(designation) = designation {
+                        s.push_str(designation);
+                    }
+                }
+                if promote {
+                    s.push('成');
+                } else if is_promotable(p, from, to) {
+                    s.push_str("不成");
+                }
+            }
+            Move::Drop { piece, .. } => {
+                s.push_str(PIECE_NAMES[piece.piece_kind().array_index()]);
+                if !self.move_candidates(piece, to).is_empty() {
+                    s.push('打');
+                }
+            }
+        }
+        s
+    }
+    /// Parses the move in the Japanese notation, optionally with `▲` or `△`.
+    /// Returns `None` if it is not a legal move or if it is ambiguous.
+    pub fn move_from_japanese(&self, s: &str) -> Option<Move> {
+        let s = s.trim().trim_start_matches(['▲', '△', '☗', '☖']);
+        let (to, s) = match s.strip_prefix('同') {
+            Some(s) => (self.last_move()?.to(), s.trim_start_matches(['　', ' '])),
+            None => parse_square(s)?,
+        };
+        let (pk, s) = parse_piece_kind(s)?;
+        let p = Piece::new(pk, self.side_to_move());
+        let designation = s
+            .chars()
+            .take_while(|c| "右左直上引寄行".contains(*c))
+            .collect::<String>();
+        let (promote, drop) = match s[designation.len()..].trim_end() {
+            "" => (false, false),
+            "成" => (true, false),
+            "不成" | "生" => (false, false),
+            "打" => (false, true),
+            _ => return None,
+        };
+        let candidates = self.move_candidates(p, to);
+        let m = if drop || (candidates.is_empty() && designation.is_empty() && !promote) {
+            Move::Drop { to, piece: p }
+        } else {
+            let mut froms = candidates.iter().filter(|&&from| {
+                designation
+                    .chars()
+                    .all(|c| designates(c, p, from, to, &candidates))
+            });
+            match (froms.next(), froms.next()) {
+                (Some(&from), None) => Move::Normal { from, to, promote },
+                _ => return None,
+            }
+        };
+        self.legal_moves().contains(&m).then_some(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::PartialPosition;
+    use shogi_usi_parser::FromUsi;
+
+    fn japanese_moves(sfen: &str, to: Square) -> Vec<String> {
+        let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+        let mut moves = pos
+            .legal_moves()
+            .into_iter()
+            .filter(|m| m.to() == to)
+            .map(|m| pos.move_to_japanese(m))
+            .collect::<Vec<_>>();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn designations() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  * +KI+KI+KI *  *  *
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  * +OU *  *  *  *
+        // P+00KI
+        // P-00AL
+        // +
+        assert_eq!(
+            vec!["５二金右", "５二金左", "５二金打", "５二金直"],
+            japanese_moves("sfen 8k/9/3GGG3/9/9/9/9/9/4K4 b G 1", Square::SQ_5B)
+        );
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  * +KI *  *  *  *  *
+        // P3 *  *  * +KI * +KI *  *  *
+        // ...
+        assert_eq!(
+            vec!["５二金右", "５二金寄", "５二金左上"],
+            japanese_moves("sfen 8k/3G5/3G1G3/9/9/9/9/9/4K4 b - 1", Square::SQ_5B)
+        );
+        // silvers on the four corners
+        assert_eq!(
+            vec!["５五銀右上", "５五銀右引", "５五銀左上", "５五銀左引"],
+            japanese_moves("sfen 8k/9/9/3S1S3/9/3S1S3/9/9/4K4 b - 1", Square::SQ_5E)
+        );
+        // dragons are not designated by 直
+        assert_eq!(
+            vec!["５二龍右", "５二龍左"],
+            japanese_moves("sfen 8k/9/3+R1+R3/9/9/9/9/9/4K4 b - 1", Square::SQ_5B)
+        );
+        // white's golds, from white's point of view
+        assert_eq!(
+            vec!["５八金右", "５八金左", "５八金直"],
+            japanese_moves("sfen 4k4/9/9/9/9/9/3ggg3/9/K8 w - 1", Square::SQ_5H)
+        );
+        // a single piece needs no designation, nor 打 for a drop
+        assert_eq!(
+            vec!["２三銀不成", "２三銀成"],
+            japanese_moves("sfen 8k/9/9/7S1/9/9/9/9/4K4 b - 1", Square::SQ_2C)
+        );
+    }
+
+    #[test]
+    fn same_square() {
+        let mut pos = Position::default();
+        for (s, expected) in [
+            ("▲７六歩", "７六歩"),
+            ("△３四歩", "３四歩"),
+            ("▲２二角成", "２二角成"),
+            ("△同　銀", "同　銀"),
+            ("▲４五角打", "４五角"),
+        ] {
+            let m = pos.move_from_japanese(s).expect("failed to parse");
+            assert_eq!(expected, pos.move_to_japanese(m));
+            pos.do_move(m);
+        }
+        assert_eq!(Some(Piece::B_B), pos.piece_at(Square::SQ_4E));
+        assert_eq!(None, pos.move_from_japanese("５二金"));
+        assert_eq!(None, pos.move_from_japanese("同歩"));
+    }
+
+    #[test]
+    fn bijection() {
+        for sfen in [
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "sfen 8k/9/9/3S1S3/9/3S1S3/9/9/4K4 b GSNLP 1",
+            "sfen 8k/3G5/3G1G3/9/9/9/9/9/4K4 b G 1",
+            "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+            "sfen 9/9/9/9/9/7p1/7+r1/6KN1/4k1S2 b Pr2b4g3s3n4l16p 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            for m in pos.legal_moves() {
+                let s = pos.move_to_japanese(m);
+                assert_eq!(Some(m), pos.move_from_japanese(&s), "{sfen} {s}");
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Reader and writer of KI2, the game record format with the moves in the Japanese notation.
+//!
+//! ```text
+//! 手合割：平手
+//! 先手：先手の名前
+//! 後手：後手の名前
+//! ▲７六歩　　　△３四歩　　　▲２二角成　　△同　銀
+//! *comment
+//! ▲４五角
+//! まで5手で先手の勝ち
+//!
+//! 変化：2手
+//! △８四歩
+//! ```
+//!
+//! The headers, the board diagram, the comments and the variations are the same as [KIF](crate::kif),
+//! but the times are not recorded, and the end of the game is recorded only as the result such as `まで5手で先手の勝ち`.
+use crate::kif::{write_headers, Parser};
+use crate::record::{Action, ParseError, Record, RecordMove, SpecialMove};
+use crate::Position;
+use shogi_core::Color;
+use std::fmt::Write;
+
+const MOVES_PER_LINE: usize = 6;
+
+/// Parses a KI2 game record. All the moves, including the ones in variations, are checked to be legal.
+pub fn parse(s: &str) -> Result<Record, ParseError> {
+    Parser::new(true).parse(s)
+}
+
+/// Serializes the record in KI2.
+///
+/// The moves are assumed to be legal: use [`Record::to_position`] to check them in advance.
+/// Special moves which are not distinguished in the results (such as [`SpecialMove::TimeUp`])
+/// are written as the win of the opponent.
+pub fn to_string(record: &Record) -> String {
+    let mut s = String::new();
+    write_headers(&mut s, record);
+    for comment in &record.comments {
+        let _ = writeln!(s, "*{comment}");
+    }
+    let mut pos = Position::new(record.initial.clone());
+    write_line(&mut s, &mut pos, &record.moves);
+    s
+}
+
+fn flush(s: &mut String, tokens: &mut Vec<String>) {
+    if let Some((last, init)) = tokens.split_last() {
+        for token in init {
+            let _ = write!(s, "{token:　<7}");
+        }
+        s.push_str(last);
+        s.push('\n');
+    }
+    tokens.clear();
+}
+
+/// Writes the line and then its variations, deepest first, in the same way as KIF.
+fn write_line(s: &mut String, pos: &mut Position, line: &[RecordMove]) {
+    let mut tokens = Vec::with_capacity(MOVES_PER_LINE);
+    let mut played = 0;
+    for record_move in line {
+        let c = pos.side_to_move();
+        match record_move.action {
+            Action::Move(m) => {
+                let mark = if c == Color::Black { '▲' } else { '△' };
+                tokens.push(format!("{mark}{}", pos.move_to_japanese(m)));
+                if tokens.len() == MOVES_PER_LINE || !record_move.comments.is_empty() {
+                    flush(s, &mut tokens);
+                }
+                pos.do_move(m);
+                played += 1;
+            }
+            Action::Special(special) => {
+                flush(s, &mut tokens);
+                let result = match special {
+                    SpecialMove::Interrupt => String::from("中断"),
+                    SpecialMove::Repetition => String::from("千日手"),
+                    SpecialMove::Jishogi => String::from("持将棋"),
+                    SpecialMove::Mate => String::from("詰み"),
+                    SpecialMove::Draw => String::from("引き分け"),
+                    _ => {
+                        let winner = match special {
+                            SpecialMove::IllegalAction(loser) => loser.flip(),
+                            SpecialMove::EnteringKing => c,
+                            _ => c.flip(),
+                        };
+                        let winner = if winner == Color::Black {
+                            "先手"
+                        } else {
+                            "後手"
+                        };
+                        format!("{winner}の勝ち")
+                    }
+                };
+                let _ = writeln!(s, "まで{}手で{result}", pos.ply() - 1);
+            }
+        }
+        for comment in &record_move.comments {
+            let _ = writeln!(s, "*{comment}");
+        }
+        if let Action::Special(_) = record_move.action {
+            break;
+        }
+    }
+    flush(s, &mut tokens);
+    for (i, record_move) in line.iter().enumerate().rev() {
+        if i < played {
+            if let Action::Move(m) = record_move.action {
+                pos.undo_move(m);
+            }
+        }
+        for variation in &record_move.variations {
+            let _ = write!(s, "\n変化：{}手\n", pos.ply());
+            write_line(s, pos, variation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::{Move, PartialPosition, Piece, Square};
+
+    const KI2: &str = "\
+開始日時：2022/01/01 10:00:00
+手合割：平手
+先手：先手太郎
+後手：後手花子
+
+▲７六歩    △３四歩    ▲２二角成  △同　銀    ▲４五角    △５二金右
+▲５八金右
+*コメント
+まで7手で先手の勝ち
+
+変化：2手
+△８四歩    ▲２六歩    △５二金右
+まで5手で中断
+";
+
+    #[test]
+    fn parse_ki2() {
+        let record = parse(KI2).expect("failed to parse");
+        assert_eq!(Some("先手太郎"), record.black_name.as_deref());
+        assert_eq!(PartialPosition::startpos(), record.initial);
+        assert_eq!(8, record.moves.len());
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_3A,
+                to: Square::SQ_2B,
+                promote: false,
+            }),
+            record.moves[3].action
+        );
+        assert_eq!(
+            Action::Move(Move::Drop {
+                to: Square::SQ_4E,
+                piece: Piece::B_B,
+            }),
+            record.moves[4].action
+        );
+        // the gold on the right from white's point of view
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_6A,
+                to: Square::SQ_5B,
+                promote: false,
+            }),
+            record.moves[5].action
+        );
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_4I,
+                to: Square::SQ_5H,
+                promote: false,
+            }),
+            record.moves[6].action
+        );
+        assert_eq!(vec![String::from("コメント")], record.moves[6].comments);
+        // resigned by white
+        assert_eq!(Action::Special(SpecialMove::Resign), record.moves[7].action);
+
+        let variation = &record.moves[1].variations[0];
+        assert_eq!(4, variation.len());
+        assert_eq!(Action::Special(SpecialMove::Interrupt), variation[3].action);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let record = parse(KI2).expect("failed to parse");
+        let s = to_string(&record);
+        assert_eq!(record, parse(&s).expect("failed to parse"));
+        assert!(s.contains(
+            "▲７六歩　　　△３四歩　　　▲２二角成　　△同　銀　　　▲４五角　　　△５二金右\n"
+        ));
+
+        let mut pos = Position::default();
+        for m in record.moves.iter().filter_map(|rm| rm.action.as_move()) {
+            pos.do_move(m);
+        }
+        assert_eq!(
+            "手合割：平手\n▲７六歩　　　△３四歩　　　▲２二角成　　△同　銀　　　▲４五角　　　△５二金右\n▲５八金右\n",
+            to_string(&Record::from_position(&pos).expect("null move"))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        use crate::record::ParseErrorKind;
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::IllegalMove
+            }),
+            parse("▲７六歩△７五歩").err()
+        );
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::InvalidMove
+            }),
+            parse("▲７六歩▲２六歩").err()
+        );
+    }
+}
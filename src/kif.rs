@@ -0,0 +1,947 @@
+//! Reader and writer of KIF, the game record format of Kifu for Windows.
+//!
+//! ```text
+//! 手合割：平手
+//! 先手：先手の名前
+//! 後手：後手の名前
+//! 手数----指手---------消費時間--
+//!    1 ７六歩(77)   ( 0:01/00:00:01)
+//!    2 ３四歩(33)   ( 0:02/00:00:02)
+//! *comment
+//!    3 投了
+//!
+//! 変化：2手
+//!    2 ８四歩(83)   ( 0:03/00:00:03)
+//! ```
+//!
+//! The initial position is either `手合割` of a standard [`Handicap`] or a board diagram (BOD).
+use crate::japanese::{
+    parse_piece_char, parse_piece_kind, parse_square, FILE_CHARS, PIECE_CHARS, PIECE_NAMES,
+    RANK_CHARS,
+};
+use crate::record::{Action, ParseError, ParseErrorKind, Record, RecordMove, SpecialMove};
+use crate::{Handicap, Position};
+use shogi_core::{Color, Hand, Move, PartialPosition, Piece, Square};
+use std::fmt::Write;
+use std::time::Duration;
+
+const MOVES_HEADER: &str = "手数----指手---------消費時間--";
+pub(crate) const BOARD_FRAME: &str = "+---------------------------+";
+/// Header of the files above the board in BOD.
+pub(crate) const FILES_HEADER: &str = "  ９ ８ ７ ６ ５ ４ ３ ２ １";
+
+#[rustfmt::skip]
+const SPECIAL_MOVES: [(&str, SpecialMove); 11] = [
+    ("投了",     SpecialMove::Resign),
+    ("中断",     SpecialMove::Interrupt),
+    ("千日手",   SpecialMove::Repetition),
+    ("切れ負け", SpecialMove::TimeUp),
+    ("反則負け", SpecialMove::IllegalMove),
+    ("持将棋",   SpecialMove::Jishogi),
+    ("入玉勝ち", SpecialMove::EnteringKing),
+    ("引き分け", SpecialMove::Draw),
+    ("待った",   SpecialMove::Matta),
+    ("詰み",     SpecialMove::Mate),
+    ("不詰",     SpecialMove::NoMate),
+];
+
+fn number_to_kanji(n: u8) -> String {
+    match n {
+        0 => String::new(),
+        1..=9 => RANK_CHARS[usize::from(n) - 1].to_string(),
+        _ => format!("十{}", number_to_kanji(n - 10)),
+    }
+}
+
+fn kanji_to_number(s: &str) -> Option<u8> {
+    match s.strip_prefix('十') {
+        Some("") => Some(10),
+        Some(rest) => kanji_to_number(rest).map(|n| n + 10),
+        None => {
+            let mut chars = s.chars();
+            let c = chars.next()?;
+            let n = RANK_CHARS.iter().position(|&rc| rc == c)?;
+            chars.as_str().is_empty().then_some(n as u8 + 1)
+        }
+    }
+}
+
+/// Parses a KIF game record. All the moves, including the ones in variations, are checked to be legal.
+pub fn parse(s: &str) -> Result<Record, ParseError> {
+    Parser::default().parse(s)
+}
+
+/// Marks of the players before the moves in KI2.
+const KI2_MARKS: [char; 4] = ['▲', '△', '☗', '☖'];
+
+#[derive(Default)]
+struct Node {
+    record_move: Option<RecordMove>,
+    children: Vec<usize>,
+}
+
+/// Parser of both KIF and KI2, which share the headers, the board diagram, the comments and the variations.
+#[derive(Default)]
+pub(crate) struct Parser {
+    /// Whether to read the moves in KI2 instead of KIF.
+    ki2: bool,
+    record: Record,
+    /// The board diagram, if any.
+    bod: Option<PartialPosition>,
+    bod_rows: u8,
+    handicap: Option<String>,
+    /// The tree of the moves, whose root is the initial position.
+    nodes: Vec<Node>,
+    /// The nodes of the line being read.
+    path: Vec<usize>,
+    pos: Option<Position>,
+}
+
+impl Parser {
+    pub(crate) fn new(ki2: bool) -> Self {
+        Self {
+            ki2,
+            ..Default::default()
+        }
+    }
+    pub(crate) fn parse(mut self, s: &str) -> Result<Record, ParseError> {
+        self.nodes.push(Node::default());
+        for (i, line) in s.lines().enumerate() {
+            self.parse_line(line.trim_end())
+                .map_err(|kind| ParseError { line: i + 1, kind })?;
+        }
+        self.start().map_err(|kind| ParseError {
+            line: s.lines().count(),
+            kind,
+        })?;
+        self.record.moves = self.line_from(0);
+        Ok(self.record)
+    }
+    fn parse_line(&mut self, line: &str) -> Result<(), ParseErrorKind> {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty()
+            || trimmed.starts_with(['#', '&'])
+            || trimmed.starts_with("手数-")
+            || trimmed.trim_end() == FILES_HEADER.trim_start()
+        {
+            return Ok(());
+        }
+        if let Some(result) = trimmed.strip_prefix("まで") {
+            // the result is recorded as the special move in KIF
+            return match self.ki2 {
+                true => self.parse_ki2_result(result),
+                false => Ok(()),
+            };
+        }
+        if let Some(comment) = line.strip_prefix('*') {
+            let comments = match self.path.last() {
+                Some(&id) => &mut self.nodes[id].record_move.as_mut().unwrap().comments,
+                None => &mut self.record.comments,
+            };
+            comments.push(comment.to_string());
+            return Ok(());
+        }
+        if !self.ki2 && is_move_line(trimmed) {
+            return self.parse_move(trimmed);
+        }
+        if self.ki2 && trimmed.starts_with(KI2_MARKS) {
+            return self.parse_ki2_moves(trimmed);
+        }
+        if let Some(rest) = line.strip_prefix("変化：") {
+            let ply = rest
+                .trim_end_matches('手')
+                .parse()
+                .map_err(|_| ParseErrorKind::InvalidVariation)?;
+            return self.branch(ply);
+        }
+        if line.starts_with('+') {
+            self.bod.get_or_insert_with(PartialPosition::empty);
+            return Ok(());
+        }
+        if let Some(row) = line.strip_prefix('|') {
+            return self.parse_board_row(row);
+        }
+        match line {
+            "先手番" | "下手番" => {
+                self.bod_mut().side_to_move_set(Color::Black);
+                return Ok(());
+            }
+            "後手番" | "上手番" => {
+                self.bod_mut().side_to_move_set(Color::White);
+                return Ok(());
+            }
+            _ => {}
+        }
+        if let Some(ply) = line.strip_prefix("手数＝") {
+            let ply = ply
+                .parse::<u16>()
+                .map_err(|_| ParseErrorKind::InvalidPosition)?;
+            return match self.bod_mut().ply_set(ply + 1) {
+                true => Ok(()),
+                false => Err(ParseErrorKind::InvalidPosition),
+            };
+        }
+        let Some((key, value)) = line.split_once('：').or_else(|| line.split_once(':')) else {
+            // unknown lines are ignored
+            return Ok(());
+        };
+        let value = value.trim().to_string();
+        match key {
+            "先手" | "下手" => self.record.black_name = Some(value),
+            "後手" | "上手" => self.record.white_name = Some(value),
+            "手合割" => self.handicap = Some(value),
+            "先手の持駒" | "下手の持駒" => self.parse_hand(Color::Black, &value)?,
+            "後手の持駒" | "上手の持駒" => self.parse_hand(Color::White, &value)?,
+            _ => self.record.headers.push((key.to_string(), value)),
+        }
+        Ok(())
+    }
+    fn bod_mut(&mut self) -> &mut PartialPosition {
+        self.bod.get_or_insert_with(PartialPosition::empty)
+    }
+    fn parse_board_row(&mut self, row: &str) -> Result<(), ParseErrorKind> {
+        let chars = row.chars().collect::<Vec<_>>();
+        if self.bod_rows >= 9 || chars.len() < 19 || chars[18] != '|' {
+            return Err(ParseErrorKind::InvalidPosition);
+        }
+        self.bod_rows += 1;
+        let rank = self.bod_rows;
+        for (i, cell) in chars[..18].chunks(2).enumerate() {
+            let sq = Square::new(9 - i as u8, rank).unwrap();
+            let piece = match cell {
+                [_, '・'] => None,
+                [' ', c] => Some(Piece::new(
+                    parse_piece_char(*c).ok_or(ParseErrorKind::InvalidPosition)?,
+                    Color::Black,
+                )),
+                ['v', c] => Some(Piece::new(
+                    parse_piece_char(*c).ok_or(ParseErrorKind::InvalidPosition)?,
+                    Color::White,
+                )),
+                _ => return Err(ParseErrorKind::InvalidPosition),
+            };
+            self.bod_mut().piece_set(sq, piece);
+        }
+        Ok(())
+    }
+    fn parse_hand(&mut self, c: Color, s: &str) -> Result<(), ParseErrorKind> {
+        let mut hand = Hand::new();
+        for item in s.split(['　', ' ']).filter(|item| !item.is_empty()) {
+            if item == "なし" {
+                continue;
+            }
+            let (pk, count) = parse_piece_kind(item)
+                .filter(|&(pk, _)| Hand::is_hand_piece(pk))
+                .ok_or(ParseErrorKind::InvalidPosition)?;
+            let count = match count {
+                "" => 1,
+                _ => kanji_to_number(count).ok_or(ParseErrorKind::InvalidPosition)?,
+            };
+            for _ in 0..count {
+                hand = hand.added(pk).ok_or(ParseErrorKind::InvalidPosition)?;
+            }
+        }
+        *self.bod_mut().hand_of_a_player_mut(c) = hand;
+        Ok(())
+    }
+    /// Fixes the initial position at the first move (or at the end of the headers).
+    fn start(&mut self) -> Result<&mut Position, ParseErrorKind> {
+        if self.pos.is_none() {
+            let initial = match (self.bod.take(), &self.handicap) {
+                (Some(bod), _) => {
+                    if self.bod_rows != 9 {
+                        return Err(ParseErrorKind::InvalidPosition);
+                    }
+                    bod
+                }
+                (None, None) => PartialPosition::startpos(),
+                (None, Some(handicap)) => Handicap::from_japanese(handicap)
+                    .ok_or(ParseErrorKind::InvalidHeader)?
+                    .to_partial_position(),
+            };
+            self.pos = Some(Position::new(initial.clone()));
+            self.record.initial = initial;
+        }
+        Ok(self.pos.as_mut().unwrap())
+    }
+    fn parse_move(&mut self, line: &str) -> Result<(), ParseErrorKind> {
+        let (number, rest) = line
+            .split_once(|c: char| c.is_whitespace())
+            .ok_or(ParseErrorKind::InvalidMove)?;
+        let number = number
+            .parse::<usize>()
+            .map_err(|_| ParseErrorKind::InvalidMove)?;
+        let prev_to = self.last_move_to()?;
+        let pos = self.start()?;
+        if number != usize::from(pos.ply()) {
+            return Err(ParseErrorKind::InvalidMove);
+        }
+        let rest = rest.trim_start();
+        let (action, rest) = if let Some(&(name, special)) = SPECIAL_MOVES
+            .iter()
+            .find(|(name, _)| rest.starts_with(name))
+        {
+            (Action::Special(special), &rest[name.len()..])
+        } else if let Some(rest) = rest.strip_prefix("反則勝ち") {
+            let special = SpecialMove::IllegalAction(pos.side_to_move().flip());
+            (Action::Special(special), rest)
+        } else {
+            let (m, rest) = parse_move_text(pos, rest, prev_to)?;
+            if !pos.legal_moves().contains(&m) {
+                return Err(ParseErrorKind::IllegalMove);
+            }
+            pos.do_move(m);
+            (Action::Move(m), rest)
+        };
+        let time = match rest.trim().trim_end_matches('+').trim_end() {
+            "" => None,
+            time => Some(parse_time(time).ok_or(ParseErrorKind::InvalidMove)?),
+        };
+        self.push(RecordMove {
+            time,
+            ..RecordMove::new(action)
+        });
+        Ok(())
+    }
+    /// Parses the moves such as `▲７六歩    △３四歩`.
+    fn parse_ki2_moves(&mut self, line: &str) -> Result<(), ParseErrorKind> {
+        let mut rest = line;
+        while let Some(mark) = rest.chars().next() {
+            let c = match mark {
+                '▲' | '☗' => Color::Black,
+                '△' | '☖' => Color::White,
+                _ => return Err(ParseErrorKind::InvalidMove),
+            };
+            let body = &rest[mark.len_utf8()..];
+            let end = body.find(KI2_MARKS).unwrap_or(body.len());
+            self.last_move_to()?;
+            let pos = self.start()?;
+            if c != pos.side_to_move() {
+                return Err(ParseErrorKind::InvalidMove);
+            }
+            let m = pos
+                .move_from_japanese(&body[..end])
+                .ok_or(ParseErrorKind::IllegalMove)?;
+            pos.do_move(m);
+            self.push(RecordMove::new(Action::Move(m)));
+            rest = &body[end..];
+        }
+        Ok(())
+    }
+    /// Parses the result such as `77手で先手の勝ち`, which follows `まで`.
+    fn parse_ki2_result(&mut self, result: &str) -> Result<(), ParseErrorKind> {
+        let result = result
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .trim_start_matches("手で");
+        self.last_move_to()?;
+        let c = self.start()?.side_to_move();
+        let special = match result {
+            "中断" => SpecialMove::Interrupt,
+            "千日手" => SpecialMove::Repetition,
+            "持将棋" => SpecialMove::Jishogi,
+            "詰み" => SpecialMove::Mate,
+            "引き分け" => SpecialMove::Draw,
+            _ if result.ends_with("勝ち") => {
+                let winner = match result.get(..6) {
+                    Some("先手" | "下手") => Color::Black,
+                    Some("後手" | "上手") => Color::White,
+                    _ => return Err(ParseErrorKind::InvalidMove),
+                };
+                if winner == c {
+                    SpecialMove::IllegalAction(c.flip())
+                } else {
+                    SpecialMove::Resign
+                }
+            }
+            _ => return Err(ParseErrorKind::InvalidMove),
+        };
+        self.push(RecordMove::new(Action::Special(special)));
+        Ok(())
+    }
+    /// Returns the destination of the last move of the line being read,
+    /// or an error if the line has already ended with a special move.
+    fn last_move_to(&self) -> Result<Option<Square>, ParseErrorKind> {
+        match self.path.last() {
+            Some(&id) => match self.nodes[id].record_move.as_ref().unwrap().action {
+                Action::Move(m) => Ok(Some(m.to())),
+                Action::Special(_) => Err(ParseErrorKind::InvalidMove),
+            },
+            None => Ok(None),
+        }
+    }
+    /// Appends the move to the line being read.
+    fn push(&mut self, record_move: RecordMove) {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            record_move: Some(record_move),
+            children: Vec::new(),
+        });
+        let parent = self.path.last().copied().unwrap_or_default();
+        self.nodes[parent].children.push(id);
+        self.path.push(id);
+    }
+    /// Goes back to the position before the `ply`-th move of the line being read.
+    fn branch(&mut self, ply: usize) -> Result<(), ParseErrorKind> {
+        let initial = usize::from(self.record.initial.ply());
+        if self.pos.is_none() || ply < initial || ply - initial >= self.path.len() {
+            return Err(ParseErrorKind::InvalidVariation);
+        }
+        let pos = self.pos.as_mut().unwrap();
+        while self.path.len() > ply - initial {
+            let id = self.path.pop().unwrap();
+            if let Action::Move(m) = self.nodes[id].record_move.as_ref().unwrap().action {
+                pos.undo_move(m);
+            }
+        }
+        Ok(())
+    }
+    /// Converts the tree into the lines, taking the first child as the main line.
+    fn line_from(&mut self, parent: usize) -> Vec<RecordMove> {
+        let mut line = Vec::new();
+        let mut children = std::mem::take(&mut self.nodes[parent].children);
+        while let Some((&first, rest)) = children.split_first() {
+            let mut record_move = self.nodes[first].record_move.take().unwrap();
+            for &id in rest {
+                let mut variation = vec![self.nodes[id].record_move.take().unwrap()];
+                variation.extend(self.line_from(id));
+                record_move.variations.push(variation);
+            }
+            line.push(record_move);
+            children = std::mem::take(&mut self.nodes[first].children);
+        }
+        line
+    }
+}
+
+/// Parses a move such as `７六歩(77)`, `同　銀(68)`, `５五角打` or `２二角成(88)`, and returns it with the rest.
+fn parse_move_text<'a>(
+    pos: &Position,
+    s: &'a str,
+    prev_to: Option<Square>,
+) -> Result<(Move, &'a str), ParseErrorKind> {
+    let (to, s) = match s.strip_prefix('同') {
+        Some(s) => (
+            prev_to.ok_or(ParseErrorKind::InvalidMove)?,
+            s.trim_start_matches(['　', ' ']),
+        ),
+        None => parse_square(s).ok_or(ParseErrorKind::InvalidMove)?,
+    };
+    let (pk, s) = parse_piece_kind(s).ok_or(ParseErrorKind::InvalidMove)?;
+    let (promote, drop, s) = if let Some(s) = s.strip_prefix("不成") {
+        (false, false, s)
+    } else if let Some(s) = s.strip_prefix('成') {
+        (true, false, s)
+    } else if let Some(s) = s.strip_prefix('打') {
+        (false, true, s)
+    } else {
+        (false, false, s)
+    };
+    let from = s.strip_prefix('(').and_then(|s| {
+        let (from, rest) = s.split_once(')')?;
+        let mut digits = from.chars().map(|c| c.to_digit(10));
+        match (digits.next(), digits.next(), digits.next()) {
+            (Some(Some(file)), Some(Some(rank)), None) => {
+                Some((Square::new(file as u8, rank as u8)?, rest))
+            }
+            _ => None,
+        }
+    });
+    match from {
+        Some((from, rest)) if !drop => {
+            if pos.piece_at(from).map(|p| p.piece_kind()) != Some(pk) {
+                return Err(ParseErrorKind::IllegalMove);
+            }
+            Ok((Move::Normal { from, to, promote }, rest))
+        }
+        None if !promote => Ok((
+            Move::Drop {
+                to,
+                piece: Piece::new(pk, pos.side_to_move()),
+            },
+            s,
+        )),
+        _ => Err(ParseErrorKind::InvalidMove),
+    }
+}
+
+/// Parses the time such as `( 0:01/00:00:01)`, and returns the time spent for the move.
+fn parse_time(s: &str) -> Option<Duration> {
+    let s = s.strip_prefix('(')?.strip_suffix(')')?;
+    let (time, _) = s.split_once('/').unwrap_or((s, ""));
+    let mut secs = 0;
+    for part in time.trim().split(':') {
+        secs = secs * 60 + part.trim().parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// Serializes the record in KIF.
+///
+/// The moves are assumed to be legal: use [`Record::to_position`] to check them in advance.
+pub fn to_string(record: &Record) -> String {
+    let mut s = String::new();
+    write_headers(&mut s, record);
+    s.push_str(MOVES_HEADER);
+    s.push('\n');
+    for comment in &record.comments {
+        let _ = writeln!(s, "*{comment}");
+    }
+    let mut pos = Position::new(record.initial.clone());
+    write_line(&mut s, &mut pos, &record.moves, [Duration::ZERO; 2], None);
+    s
+}
+
+/// Writes the headers and the initial position, which are common to KIF and KI2.
+pub(crate) fn write_headers(s: &mut String, record: &Record) {
+    for (key, value) in &record.headers {
+        let _ = writeln!(s, "{key}：{value}");
+    }
+    let initial = &record.initial;
+    match Handicap::detect(initial).filter(|_| initial.ply() == 1) {
+        Some(handicap) => {
+            let _ = writeln!(s, "手合割：{}", handicap.japanese());
+        }
+        None => write_bod(s, initial),
+    }
+    if let Some(name) = &record.black_name {
+        let _ = writeln!(s, "先手：{name}");
+    }
+    if let Some(name) = &record.white_name {
+        let _ = writeln!(s, "後手：{name}");
+    }
+}
+
+/// Formats the pieces in hand as in BOD, such as `飛　金二　`, or `なし` if empty.
+pub(crate) fn hand_to_japanese(hand: Hand) -> String {
+    let mut items = String::new();
+    for pk in Hand::all_hand_pieces()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        match hand.count(pk).unwrap_or_default() {
+            0 => {}
+            1 => items.push_str(&format!("{}　", PIECE_NAMES[pk.array_index()])),
+            n => items.push_str(&format!(
+                "{}{}　",
+                PIECE_NAMES[pk.array_index()],
+                number_to_kanji(n)
+            )),
+        }
+    }
+    if items.is_empty() {
+        items.push_str("なし");
+    }
+    items
+}
+
+/// Returns whether the line starts with the move number followed by a space, such as `1 ７六歩(77)`.
+fn is_move_line(line: &str) -> bool {
+    line.split_once(char::is_whitespace)
+        .is_some_and(|(number, rest)| {
+            !number.is_empty()
+                && number.bytes().all(|b| b.is_ascii_digit())
+                && !rest.trim_start().is_empty()
+        })
+}
+
+fn write_bod(s: &mut String, pos: &PartialPosition) {
+    let hand = |c: Color| hand_to_japanese(pos.hand_of_a_player(c));
+    let _ = writeln!(s, "後手の持駒：{}", hand(Color::White));
+    s.push_str(FILES_HEADER);
+    s.push('\n');
+    s.push_str(BOARD_FRAME);
+    s.push('\n');
+    for rank in 1..=9 {
+        s.push('|');
+        for file in (1..=9).rev() {
+            match pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(p) => {
+                    s.push(if p.color() == Color::Black { ' ' } else { 'v' });
+                    s.push(PIECE_CHARS[p.piece_kind().array_index()]);
+                }
+                None => s.push_str(" ・"),
+            }
+        }
+        let _ = writeln!(s, "|{}", RANK_CHARS[usize::from(rank) - 1]);
+    }
+    s.push_str(BOARD_FRAME);
+    s.push('\n');
+    let _ = writeln!(s, "先手の持駒：{}", hand(Color::Black));
+    if pos.side_to_move() == Color::White {
+        s.push_str("後手番\n");
+    }
+    if pos.ply() > 1 {
+        let _ = writeln!(s, "手数＝{}", pos.ply() - 1);
+    }
+}
+
+/// Writes the line and then its variations, deepest first, so that each `変化` branches from the line written last.
+fn write_line(
+    s: &mut String,
+    pos: &mut Position,
+    line: &[RecordMove],
+    mut totals: [Duration; 2],
+    mut prev_to: Option<Square>,
+) {
+    let mut states = Vec::with_capacity(line.len());
+    for record_move in line {
+        states.push((totals, prev_to));
+        let c = pos.side_to_move();
+        let text = match record_move.action {
+            Action::Move(m) => move_text(pos, m, prev_to),
+            Action::Special(SpecialMove::IllegalAction(loser)) if loser != c => {
+                String::from("反則勝ち")
+            }
+            Action::Special(SpecialMove::IllegalAction(_)) => String::from("反則負け"),
+            Action::Special(special) => SPECIAL_MOVES
+                .iter()
+                .find(|&&(_, sm)| sm == special)
+                .map_or("中断", |(name, _)| name)
+                .to_string(),
+        };
+        let _ = write!(s, "{:>4} {text}", pos.ply());
+        if let Some(time) = record_move.time {
+            totals[c.array_index()] += time;
+            let total = totals[c.array_index()].as_secs();
+            let _ = write!(
+                s,
+                "   ({:>2}:{:02}/{:02}:{:02}:{:02})",
+                time.as_secs() / 60,
+                time.as_secs() % 60,
+                total / 3600,
+                total / 60 % 60,
+                total % 60
+            );
+        }
+        if !record_move.variations.is_empty() {
+            s.push('+');
+        }
+        s.push('\n');
+        for comment in &record_move.comments {
+            let _ = writeln!(s, "*{comment}");
+        }
+        match record_move.action {
+            Action::Move(m) => {
+                pos.do_move(m);
+                prev_to = Some(m.to());
+            }
+            Action::Special(_) => break,
+        }
+    }
+    for (record_move, &(totals, prev_to)) in line.iter().zip(&states).rev() {
+        if let Action::Move(m) = record_move.action {
+            pos.undo_move(m);
+        }
+        for variation in &record_move.variations {
+            let _ = write!(s, "\n変化：{}手\n", pos.ply());
+            write_line(s, pos, variation, totals, prev_to);
+        }
+    }
+}
+
+/// Formats the move such as `７六歩(77)`.
+fn move_text(pos: &Position, m: Move, prev_to: Option<Square>) -> String {
+    let mut s = if prev_to == Some(m.to()) {
+        String::from("同　")
+    } else {
+        format!(
+            "{}{}",
+            FILE_CHARS[usize::from(m.to().file()) - 1],
+            RANK_CHARS[usize::from(m.to().rank()) - 1]
+        )
+    };
+    match m {
+        Move::Normal { from, to, promote } => {
+            let piece = pos.piece_at(from);
+            if let Some(p) = piece {
+                s.push_str(PIECE_NAMES[p.piece_kind().array_index()]);
+            }
+            let promotable = piece.is_some_and(|p| {
+                p.promote().is_some()
+                    && (from.relative_rank(p.color()) <= 3 || to.relative_rank(p.color()) <= 3)
+            });
+            if promote {
+                s.push('成');
+            } else if promotable {
+                s.push_str("不成");
+            }
+            let _ = write!(s, "({}{})", from.file(), from.rank());
+        }
+        Move::Drop { piece, .. } => {
+            s.push_str(PIECE_NAMES[piece.piece_kind().array_index()]);
+            s.push('打');
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::PieceKind;
+
+    const KIF: &str = "\
+# ---- Kifu for Windows V7 棋譜ファイル ----
+開始日時：2022/01/01 10:00:00
+棋戦：テスト
+手合割：平手
+先手：先手太郎
+後手：後手花子
+手数----指手---------消費時間--
+*対局前のコメント
+   1 ７六歩(77)   ( 0:01/00:00:01)
+   2 ３四歩(33)   ( 0:02/00:00:02)+
+*角道を開ける
+   3 ２二角成(88)   ( 0:03/00:00:04)
+   4 同　銀(31)   ( 0:04/00:00:06)
+   5 ４五角打   ( 1:05/00:01:09)
+   6 投了   ( 0:06/00:00:12)
+まで5手で先手の勝ち
+
+変化：2手
+   2 ８四歩(83)   ( 0:10/00:00:10)
+   3 ２六歩(27)   ( 0:01/00:00:02)+
+   4 中断   ( 0:00/00:00:10)
+
+変化：3手
+   3 ６八銀(79)   ( 0:02/00:00:03)
+";
+
+    #[test]
+    fn parse_kif() {
+        let record = parse(KIF).expect("failed to parse");
+        assert_eq!(Some("先手太郎"), record.black_name.as_deref());
+        assert_eq!(Some("後手花子"), record.white_name.as_deref());
+        assert_eq!(
+            vec![
+                (
+                    String::from("開始日時"),
+                    String::from("2022/01/01 10:00:00")
+                ),
+                (String::from("棋戦"), String::from("テスト")),
+            ],
+            record.headers
+        );
+        assert_eq!(PartialPosition::startpos(), record.initial);
+        assert_eq!(vec![String::from("対局前のコメント")], record.comments);
+        assert_eq!(6, record.moves.len());
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_8H,
+                to: Square::SQ_2B,
+                promote: true,
+            }),
+            record.moves[2].action
+        );
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_3A,
+                to: Square::SQ_2B,
+                promote: false,
+            }),
+            record.moves[3].action
+        );
+        assert_eq!(
+            Action::Move(Move::Drop {
+                to: Square::SQ_4E,
+                piece: Piece::B_B,
+            }),
+            record.moves[4].action
+        );
+        assert_eq!(Some(Duration::from_secs(65)), record.moves[4].time);
+        assert_eq!(Action::Special(SpecialMove::Resign), record.moves[5].action);
+        assert_eq!(vec![String::from("角道を開ける")], record.moves[1].comments);
+
+        // variations
+        assert_eq!(1, record.moves[1].variations.len());
+        let variation = &record.moves[1].variations[0];
+        assert_eq!(3, variation.len());
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_8C,
+                to: Square::SQ_8D,
+                promote: false,
+            }),
+            variation[0].action
+        );
+        assert_eq!(1, variation[1].variations.len());
+        assert_eq!(
+            Action::Move(Move::Normal {
+                from: Square::SQ_7I,
+                to: Square::SQ_6H,
+                promote: false,
+            }),
+            variation[1].variations[0][0].action
+        );
+        assert_eq!(Action::Special(SpecialMove::Interrupt), variation[2].action);
+
+        let pos = record.to_position().expect("failed to replay");
+        assert_eq!(Color::White, pos.side_to_move());
+        assert_eq!(Some(Piece::B_B), pos.piece_at(Square::SQ_4E));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let record = parse(KIF).expect("failed to parse");
+        let s = to_string(&record);
+        assert_eq!(record, parse(&s).expect("failed to parse"));
+        assert!(s.contains("   4 同　銀(31)   ( 0:04/00:00:06)\n"));
+        assert!(s.contains("   2 ３四歩(33)   ( 0:02/00:00:02)+\n"));
+
+        // a played position
+        let mut pos = Position::default();
+        for m in record.moves.iter().filter_map(|rm| rm.action.as_move()) {
+            pos.do_move(m);
+        }
+        let s = to_string(&Record::from_position(&pos).expect("null move"));
+        assert_eq!(
+            "\
+手合割：平手
+手数----指手---------消費時間--
+   1 ７六歩(77)
+   2 ３四歩(33)
+   3 ２二角成(88)
+   4 同　銀(31)
+   5 ４五角打
+",
+            s
+        );
+    }
+
+    #[test]
+    fn bod() {
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // P2 *  *  *  *  *  *  *  * -KY
+        // P3 *  *  *  *  *  *  * +FU-FU
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  *  *  *  *  *  *
+        // P+00KI00KE
+        // P-00AL
+        // +
+        let kif = "\
+後手の持駒：飛二　角二　金三　銀四　桂二　香三　歩十六
+  ９ ８ ７ ６ ５ ４ ３ ２ １
++---------------------------+
+| ・ ・ ・ ・ ・ ・ ・v桂v玉|一
+| ・ ・ ・ ・ ・ ・ ・ ・v香|二
+| ・ ・ ・ ・ ・ ・ ・ 歩v歩|三
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|四
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|五
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|六
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|七
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|八
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|九
++---------------------------+
+先手の持駒：金　桂
+手数----指手---------消費時間--
+   1 ２二歩成(23)
+   2 同　玉(11)
+   3 ２三金打
+   4 １一玉(22)
+   5 中断
+";
+        let record = parse(kif).expect("failed to parse");
+        let initial = &record.initial;
+        assert_eq!(Some(Piece::W_K), initial.piece_at(Square::SQ_1A));
+        assert_eq!(Some(Piece::B_P), initial.piece_at(Square::SQ_2C));
+        assert_eq!(Some(1), initial.hand(Piece::B_G));
+        assert_eq!(Some(16), initial.hand(Piece::W_P));
+        assert_eq!(Some(3), initial.hand(Piece::W_G));
+        assert_eq!(Color::Black, initial.side_to_move());
+        let pos = record.to_position().expect("failed to replay");
+        assert_eq!(Some(Piece::W_K), pos.piece_at(Square::SQ_1A));
+        assert_eq!(Some(17), pos.hand(Color::White).count(PieceKind::Pawn));
+        assert!(!pos.in_check());
+        assert_eq!(record, parse(&to_string(&record)).expect("failed to parse"));
+        assert!(to_string(&record)
+            .contains("後手の持駒：飛二　角二　金三　銀四　桂二　香三　歩十六　\n"));
+    }
+
+    #[test]
+    fn files_header() {
+        let record =
+            parse("９番勝負：第1局\n  ９ ８ ７ ６ ５ ４ ３ ２ １\n").expect("failed to parse");
+        assert_eq!(
+            vec![(String::from("９番勝負"), String::from("第1局"))],
+            record.headers
+        );
+    }
+
+    #[test]
+    fn digit_leading_headers() {
+        let record =
+            parse("9番勝負：第1局\n2022年の対局\n   1 ７六歩(77)\n").expect("failed to parse");
+        assert_eq!(
+            vec![(String::from("9番勝負"), String::from("第1局"))],
+            record.headers
+        );
+        assert_eq!(1, record.moves.len());
+    }
+
+    #[test]
+    fn handicap() {
+        let kif = "\
+手合割：二枚落ち
+手数----指手---------消費時間--
+   1 ６二銀(71)
+   2 ７六歩(77)
+";
+        let record = parse(kif).expect("failed to parse");
+        assert_eq!(Handicap::TwoPieces.to_partial_position(), record.initial);
+        let pos = record.to_position().expect("failed to replay");
+        assert_eq!(Some(Piece::W_S), pos.piece_at(Square::SQ_6B));
+        let s = to_string(&record);
+        assert!(s.starts_with("手合割：二枚落ち\n"));
+        assert_eq!(record, parse(&s).expect("failed to parse"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |s: &str| parse(s).err();
+        // illegal move
+        assert_eq!(
+            Some(ParseError {
+                line: 3,
+                kind: ParseErrorKind::IllegalMove
+            }),
+            error("手合割：平手\n   1 ７六歩(77)\n   2 ７五歩(76)\n")
+        );
+        // wrong piece
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::IllegalMove
+            }),
+            error("   1 ７六銀(77)\n")
+        );
+        // wrong number
+        assert_eq!(
+            Some(ParseError {
+                line: 2,
+                kind: ParseErrorKind::InvalidMove
+            }),
+            error("   1 ７六歩(77)\n   3 ３四歩(33)\n")
+        );
+        // variation without the move to branch from
+        assert_eq!(
+            Some(ParseError {
+                line: 3,
+                kind: ParseErrorKind::InvalidVariation
+            }),
+            error("   1 ７六歩(77)\n\n変化：2手\n")
+        );
+        // unsupported handicap without a board diagram
+        assert_eq!(
+            Some(ParseError {
+                line: 1,
+                kind: ParseErrorKind::InvalidHeader
+            }),
+            error("手合割：右香落ち\n")
+        );
+    }
+}
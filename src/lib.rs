@@ -1,7 +1,39 @@
 mod bitboard;
+mod bitstream;
+pub mod csa;
+mod display;
+pub mod features;
+pub mod handicap;
+pub mod hcp;
+mod japanese;
+pub mod ki2;
+pub mod kif;
+mod mate;
+pub mod mcts;
 mod movegen;
+#[cfg(feature = "nnue")]
+pub mod nnue;
+pub mod packed_sfen;
+pub mod perft;
+pub mod policy;
 mod position;
+pub mod random;
+pub mod record;
+#[cfg(feature = "reference-movegen")]
+pub mod reference;
+pub mod search;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod smp;
 mod tables;
+#[cfg(test)]
+mod testing;
+pub mod transform;
+pub mod tsume;
+pub mod tt;
+pub mod usi;
 mod zobrist;
 
-pub use position::Position;
+pub use display::Diagram;
+pub use handicap::Handicap;
+pub use position::{Inconsistency, Position, Repetition};
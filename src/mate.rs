@@ -0,0 +1,112 @@
+//! Checkmate detection by checks only, shallower and cheaper than the [`tsume`](crate::tsume) solver.
+use crate::Position;
+use shogi_core::Move;
+
+impl Position {
+    /// Returns a move to checkmate the opponent, if any.
+    pub fn mate_move(&self) -> Option<Move> {
+        let mut pos = self.clone();
+        pos.checks().into_iter().find(|&m| pos.is_checkmate_move(m))
+    }
+    /// Returns whether the move is a brinkmate (必至) of `depth`: whatever the opponent replies,
+    /// the side to move can checkmate with checks in at most `depth` moves.
+    ///
+    /// With `depth` 1, every reply allows a mate in one. A checkmate is also a brinkmate,
+    /// and repetitions are not taken into account.
+    pub fn is_brinkmate_move(&self, m: Move, depth: u32) -> bool {
+        let mut pos = self.clone();
+        pos.do_move(m);
+        pos.legal_moves().into_iter().all(|reply| {
+            pos.do_move(reply);
+            let mate = pos.has_mate(depth);
+            pos.undo_move(reply);
+            mate
+        })
+    }
+    /// Returns whether the side to move can checkmate with checks in at most `depth` moves.
+    fn has_mate(&mut self, depth: u32) -> bool {
+        if depth == 0 {
+            return false;
+        }
+        self.checks().into_iter().any(|m| {
+            self.do_move(m);
+            let evasions = self.legal_moves();
+            let mate = evasions.is_empty()
+                || (depth > 1
+                    && evasions.into_iter().all(|evasion| {
+                        self.do_move(evasion);
+                        let mate = self.has_mate(depth - 1);
+                        self.undo_move(evasion);
+                        mate
+                    }));
+            self.undo_move(m);
+            mate
+        })
+    }
+    fn is_checkmate_move(&mut self, m: Move) -> bool {
+        self.do_move(m);
+        let mate = self.legal_moves().is_empty();
+        self.undo_move(m);
+        mate
+    }
+    fn checks(&self) -> Vec<Move> {
+        self.legal_moves()
+            .into_iter()
+            .filter(|&m| self.is_check_move(m))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::{Piece, Square};
+
+    #[test]
+    fn mate_move() {
+        let pos = position(MATE_IN_ONE);
+        assert_eq!(
+            Some(Move::Drop {
+                to: Square::SQ_2B,
+                piece: Piece::B_G,
+            }),
+            pos.mate_move()
+        );
+        assert_eq!(None, Position::default().mate_move());
+        // pawn drop mate is not allowed
+        let pos = position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        assert_eq!(None, pos.mate_move());
+    }
+
+    #[test]
+    fn brinkmate() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // ...
+        // P9 *  *  *  * +OU *  *  *  *
+        // P+00KI00KI
+        // +
+        let pos = position("sfen 8k/9/9/9/9/9/9/9/4K4 b 2G 1");
+        let drop = |to| Move::Drop {
+            to,
+            piece: Piece::B_G,
+        };
+        assert!(pos.is_brinkmate_move(drop(Square::SQ_1C), 1));
+        assert!(pos.is_brinkmate_move(drop(Square::SQ_2C), 1));
+        assert!(!pos.is_brinkmate_move(drop(Square::SQ_3C), 1));
+        assert!(pos.is_brinkmate_move(drop(Square::SQ_3C), 2));
+        assert!(!pos.is_brinkmate_move(drop(Square::SQ_5E), 2));
+
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // ...
+        // the knight captures the gold on 1c
+        let pos = position("sfen 7nk/9/9/9/9/9/9/9/4K4 b 2G 1");
+        assert!(!pos.is_brinkmate_move(drop(Square::SQ_1C), 1));
+        assert!(pos.is_brinkmate_move(drop(Square::SQ_2C), 1));
+
+        // a checkmate is also a brinkmate
+        let pos = position(MATE_IN_ONE);
+        assert!(pos.is_brinkmate_move(drop(Square::SQ_2B), 1));
+    }
+}
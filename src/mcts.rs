@@ -0,0 +1,443 @@
+//! Monte Carlo tree search with PUCT, for engines guided by policy and value networks.
+//!
+//! The leaves are evaluated in batches by a user callback, which returns the policy priors
+//! over [`Position::legal_moves`] and the value of each position. Virtual losses keep the
+//! playouts of a batch away from each other.
+use crate::{Position, Repetition};
+use shogi_core::Move;
+use std::ops::Range;
+
+/// Result of the evaluation of a leaf position.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Evaluation {
+    /// Prior probabilities of the moves, in the order of [`Position::legal_moves`].
+    /// They are normalized, and uniform priors are used if the length doesn't match.
+    pub policy: Vec<f32>,
+    /// Expected result in `[-1, 1]` from the side to move's point of view.
+    pub value: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Weight of the exploration term.
+    pub c_puct: f32,
+    /// Maximum number of the positions evaluated at once.
+    pub batch_size: usize,
+    /// Number of the losses added temporarily to the nodes on the path of a pending playout.
+    pub virtual_loss: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            c_puct: 1.5,
+            batch_size: 8,
+            virtual_loss: 1,
+        }
+    }
+}
+
+/// Statistics of a move from the root.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChildStats {
+    pub m: Move,
+    pub prior: f32,
+    pub visits: u32,
+    /// Mean value from the side to move's point of view at the root.
+    pub q: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    /// Move from the parent, `None` for the initial root
+    m: Option<Move>,
+    prior: f32,
+    visits: u32,
+    /// Sum of the values from the point of view of the player who made the move
+    value_sum: f32,
+    virtual_loss: u32,
+    /// Indices of the children in the arena, empty until expanded
+    children: Range<usize>,
+    expanded: bool,
+    /// Value for the side to move if the game is over at this node
+    terminal: Option<f32>,
+}
+
+impl Node {
+    fn new(m: Option<Move>, prior: f32) -> Self {
+        Self {
+            m,
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            virtual_loss: 0,
+            children: 0..0,
+            expanded: false,
+            terminal: None,
+        }
+    }
+    fn q(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.value_sum / self.visits as f32
+        }
+    }
+}
+
+/// Search tree of PUCT, whose nodes are stored in an arena.
+pub struct Mcts {
+    pos: Position,
+    config: Config,
+    nodes: Vec<Node>,
+}
+
+impl Mcts {
+    const ROOT: usize = 0;
+
+    pub fn new(pos: Position, config: Config) -> Self {
+        Self {
+            pos,
+            config,
+            nodes: vec![Node::new(None, 1.0)],
+        }
+    }
+    /// Returns the position at the root.
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// Returns the number of the playouts through the root.
+    pub fn visits(&self) -> u32 {
+        self.nodes[Self::ROOT].visits
+    }
+    /// Returns the mean value at the root from the side to move's point of view.
+    pub fn value(&self) -> f32 {
+        -self.nodes[Self::ROOT].q()
+    }
+    pub fn children(&self) -> Vec<ChildStats> {
+        self.nodes[self.nodes[Self::ROOT].children.clone()]
+            .iter()
+            .filter_map(|node| {
+                Some(ChildStats {
+                    m: node.m?,
+                    prior: node.prior,
+                    visits: node.visits,
+                    q: node.q(),
+                })
+            })
+            .collect()
+    }
+    /// Returns the most visited move.
+    pub fn best_move(&self) -> Option<Move> {
+        self.best_child(Self::ROOT)
+            .and_then(|child| self.nodes[child].m)
+    }
+    /// Returns the sequence of the most visited moves.
+    pub fn pv(&self) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut id = Self::ROOT;
+        while let Some(child) = self.best_child(id) {
+            pv.extend(self.nodes[child].m);
+            id = child;
+        }
+        pv
+    }
+    /// Runs the playouts, calling `evaluate` with at most [`Config::batch_size`] positions at once.
+    /// `evaluate` must return an [`Evaluation`] for each of the positions.
+    pub fn run<F>(&mut self, playouts: usize, mut evaluate: F)
+    where
+        F: FnMut(&[Position]) -> Vec<Evaluation>,
+    {
+        let mut done = 0;
+        while done < playouts {
+            let (mut paths, mut positions) = (Vec::new(), Vec::new());
+            while paths.len() < self.config.batch_size.max(1).min(playouts - done) {
+                let (path, pos) = self.select();
+                let leaf = path[path.len() - 1];
+                if let Some(value) = self.terminal_value(leaf, &pos) {
+                    self.backup(&path, value);
+                    done += 1;
+                    continue;
+                }
+                // the leaf is already pending, so that the batch is full
+                if paths.iter().any(|p: &Vec<usize>| p.last() == Some(&leaf)) {
+                    self.remove_virtual_loss(&path);
+                    break;
+                }
+                paths.push(path);
+                positions.push(pos);
+            }
+            if paths.is_empty() {
+                continue;
+            }
+            let evaluations = evaluate(&positions);
+            assert_eq!(paths.len(), evaluations.len(), "missing evaluations");
+            for ((path, pos), evaluation) in paths.iter().zip(&positions).zip(evaluations) {
+                self.expand(path[path.len() - 1], pos, &evaluation.policy);
+                self.backup(path, evaluation.value);
+                done += 1;
+            }
+        }
+    }
+    /// Moves the root to the position after the move, keeping the subtree below it.
+    pub fn advance(&mut self, m: Move) {
+        let child = self.nodes[self.nodes[Self::ROOT].children.clone()]
+            .iter()
+            .position(|node| node.m == Some(m))
+            .map(|i| self.nodes[Self::ROOT].children.start + i);
+        self.pos.do_move(m);
+        let Some(child) = child else {
+            self.nodes = vec![Node::new(Some(m), 1.0)];
+            return;
+        };
+        // copy the subtree in breadth-first order, so that the children stay contiguous
+        let mut nodes = vec![self.nodes[child].clone()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let start = nodes.len();
+            nodes.extend_from_slice(&self.nodes[nodes[i].children.clone()]);
+            nodes[i].children = start..nodes.len();
+            i += 1;
+        }
+        // a repetition is not the end of the game at the root
+        nodes[Self::ROOT].terminal = None;
+        self.nodes = nodes;
+    }
+    /// Descends from the root by PUCT to a leaf, adding virtual losses on the path.
+    fn select(&mut self) -> (Vec<usize>, Position) {
+        let mut pos = self.pos.clone();
+        let mut path = vec![Self::ROOT];
+        let mut id = Self::ROOT;
+        while self.nodes[id].expanded && self.nodes[id].terminal.is_none() {
+            id = self.select_child(id);
+            pos.do_move(self.nodes[id].m.expect("no move"));
+            path.push(id);
+        }
+        for &id in &path {
+            self.nodes[id].virtual_loss += self.config.virtual_loss;
+        }
+        (path, pos)
+    }
+    fn select_child(&self, id: usize) -> usize {
+        let parent = &self.nodes[id];
+        let sqrt_n = ((parent.visits + parent.virtual_loss) as f32).sqrt();
+        let score = |child: &Node| {
+            let n = child.visits + child.virtual_loss;
+            let q = if n == 0 {
+                0.0
+            } else {
+                (child.value_sum - child.virtual_loss as f32) / n as f32
+            };
+            q + self.config.c_puct * child.prior * sqrt_n / (1 + n) as f32
+        };
+        parent
+            .children
+            .clone()
+            .max_by(|&a, &b| score(&self.nodes[a]).total_cmp(&score(&self.nodes[b])))
+            .expect("no children")
+    }
+    /// Returns the value for the side to move if the game is over at the node.
+    fn terminal_value(&mut self, id: usize, pos: &Position) -> Option<f32> {
+        let node = &self.nodes[id];
+        if node.expanded || node.terminal.is_some() {
+            return node.terminal;
+        }
+        let terminal = match (id != Self::ROOT).then(|| pos.repetition()).flatten() {
+            Some(Repetition::Draw) => Some(0.0),
+            Some(Repetition::Win) => Some(1.0),
+            Some(Repetition::Loss) => Some(-1.0),
+            // no legal moves is a loss in shogi, even if not in check
+            None => pos.legal_moves().is_empty().then_some(-1.0),
+        };
+        self.nodes[id].terminal = terminal;
+        terminal
+    }
+    fn expand(&mut self, id: usize, pos: &Position, policy: &[f32]) {
+        let moves = pos.legal_moves();
+        let sum = policy.iter().sum::<f32>();
+        let uniform = policy.len() != moves.len() || sum <= 0.0 || !sum.is_finite();
+        let start = self.nodes.len();
+        self.nodes.extend(moves.iter().enumerate().map(|(i, &m)| {
+            let prior = if uniform {
+                1.0 / moves.len() as f32
+            } else {
+                policy[i] / sum
+            };
+            Node::new(Some(m), prior)
+        }));
+        let end = self.nodes.len();
+        let node = &mut self.nodes[id];
+        node.children = start..end;
+        node.expanded = true;
+    }
+    /// Propagates the value for the side to move at the leaf, removing the virtual losses.
+    fn backup(&mut self, path: &[usize], value: f32) {
+        let mut value = -value;
+        for &id in path.iter().rev() {
+            let node = &mut self.nodes[id];
+            node.visits += 1;
+            node.value_sum += value;
+            node.virtual_loss -= self.config.virtual_loss;
+            value = -value;
+        }
+    }
+    fn remove_virtual_loss(&mut self, path: &[usize]) {
+        for &id in path {
+            self.nodes[id].virtual_loss -= self.config.virtual_loss;
+        }
+    }
+    fn best_child(&self, id: usize) -> Option<usize> {
+        self.nodes[id]
+            .children
+            .clone()
+            .filter(|&child| self.nodes[child].visits > 0)
+            .max_by(|&a, &b| {
+                let (a, b) = (&self.nodes[a], &self.nodes[b]);
+                a.visits.cmp(&b.visits).then(a.q().total_cmp(&b.q()))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::{PartialPosition, Piece, Square};
+    use shogi_usi_parser::FromUsi;
+
+    /// Evaluates every position as even, with uniform priors.
+    fn dummy(positions: &[Position]) -> Vec<Evaluation> {
+        vec![Evaluation::default(); positions.len()]
+    }
+
+    #[test]
+    fn playouts() {
+        let mut mcts = Mcts::new(Position::default(), Config::default());
+        let mut batches = Vec::new();
+        mcts.run(100, |positions| {
+            batches.push(positions.len());
+            dummy(positions)
+        });
+        assert_eq!(100, mcts.visits());
+        assert_eq!(100, batches.iter().sum::<usize>());
+        assert!(batches.iter().all(|&n| (1..=8).contains(&n)));
+        // batched playouts are spread by the virtual losses
+        assert!(batches.iter().any(|&n| n > 1));
+        let children = mcts.children();
+        assert_eq!(30, children.len());
+        assert!(children.iter().all(|child| child.prior == 1.0 / 30.0));
+        assert_eq!(99, children.iter().map(|child| child.visits).sum::<u32>());
+        assert!(mcts.best_move().is_some());
+        assert!(!mcts.pv().is_empty());
+        assert!(mcts.nodes.iter().all(|node| node.virtual_loss == 0));
+    }
+
+    #[test]
+    fn policy() {
+        let mut mcts = Mcts::new(Position::default(), Config::default());
+        // prefers the last legal move
+        mcts.run(50, |positions| {
+            positions
+                .iter()
+                .map(|pos| {
+                    let n = pos.legal_moves().len();
+                    let mut policy = vec![0.0; n];
+                    policy[n - 1] = 2.0;
+                    Evaluation { policy, value: 0.0 }
+                })
+                .collect()
+        });
+        let m = Position::default().legal_moves().last().copied();
+        assert_eq!(m, mcts.best_move());
+        assert_eq!(Some(1.0), mcts.children().last().map(|child| child.prior));
+    }
+
+    #[test]
+    fn terminal() {
+        let pos = position(MATE_IN_ONE);
+        let mut mcts = Mcts::new(pos, Config::default());
+        mcts.run(500, dummy);
+        let m = Move::Drop {
+            to: Square::SQ_2B,
+            piece: Piece::B_G,
+        };
+        assert_eq!(Some(m), mcts.best_move());
+        assert_eq!(vec![m], mcts.pv());
+        let child = mcts.children().into_iter().find(|child| child.m == m);
+        assert_eq!(Some(1.0), child.map(|child| child.q));
+
+        // no legal moves at the root
+        mcts.advance(m);
+        let visits = mcts.visits();
+        mcts.run(10, dummy);
+        assert_eq!(visits + 10, mcts.visits());
+        assert_eq!(-1.0, mcts.value());
+        assert_eq!(None, mcts.best_move());
+    }
+
+    #[test]
+    fn repetition() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // ...
+        // P9+OU *  *  *  *  *  * +HI *
+        // perpetual checks by the rook lose for black
+        let mut pos = Position::new(
+            PartialPosition::from_usi("sfen 8k/9/9/9/9/9/9/9/K6R1 b - 1").expect("failed to parse"),
+        );
+        for (from, to) in [
+            (Square::SQ_2I, Square::SQ_1I),
+            (Square::SQ_1A, Square::SQ_2A),
+            (Square::SQ_1I, Square::SQ_2I),
+        ] {
+            pos.do_move(Move::Normal {
+                from,
+                to,
+                promote: false,
+            });
+        }
+        let mut mcts = Mcts::new(pos, Config::default());
+        mcts.run(200, dummy);
+        let back = Move::Normal {
+            from: Square::SQ_2A,
+            to: Square::SQ_1A,
+            promote: false,
+        };
+        // white wants to repeat, since black has been checking
+        let child = mcts.children().into_iter().find(|child| child.m == back);
+        assert_eq!(Some(1.0), child.map(|child| child.q));
+        assert_eq!(Some(back), mcts.best_move());
+    }
+
+    #[test]
+    fn tree_reuse() {
+        let mut mcts = Mcts::new(Position::default(), Config::default());
+        mcts.run(200, dummy);
+        let m = mcts.best_move().expect("no best move");
+        let child = mcts
+            .children()
+            .into_iter()
+            .find(|child| child.m == m)
+            .expect("no child");
+        let count = mcts.node_count();
+        mcts.advance(m);
+        assert_eq!(child.visits, mcts.visits());
+        assert!(mcts.node_count() < count);
+        assert_eq!(Some(m), mcts.position().last_move());
+        mcts.run(100, dummy);
+        assert_eq!(child.visits + 100, mcts.visits());
+        assert_eq!(
+            child.visits + 99,
+            mcts.children().iter().map(|c| c.visits).sum::<u32>()
+        );
+
+        // not visited yet
+        let mut mcts = Mcts::new(Position::default(), Config::default());
+        mcts.run(1, dummy);
+        mcts.advance(mcts.position().legal_moves()[0]);
+        assert_eq!(0, mcts.visits());
+        assert_eq!(1, mcts.node_count());
+    }
+}
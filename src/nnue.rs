@@ -0,0 +1,368 @@
+//! HalfKP features of NNUE evaluation functions, in the same layout as [YaneuraOu](https://github.com/yaneurao/YaneuraOu).
+//!
+//! A feature is a pair of the square of the king and a `BonaPiece`, which is a piece on a square or
+//! the `n`-th piece of a kind in hand, seen from the viewpoint of either side.
+//! Positions record the changed pieces of each move in [`Position::do_move`],
+//! so that accumulators can be updated incrementally with [`FeatureTransformer::update`].
+use crate::Position;
+use arrayvec::ArrayVec;
+use shogi_core::{Color, Hand, Move, Piece, PieceKind, Square};
+
+/// Number of `BonaPiece`s.
+pub const FE_END: usize = 1548;
+/// Number of HalfKP features for each side.
+pub const HALFKP_DIMENSIONS: usize = Square::NUM * FE_END;
+
+/// `BonaPiece` offsets of the pieces in hand, for friend and enemy.
+#[rustfmt::skip]
+const HAND_OFFSETS: [(usize, usize); 7] = [
+    (1, 20),  // Pawn
+    (39, 44), // Lance
+    (49, 54), // Knight
+    (59, 64), // Silver
+    (69, 74), // Gold
+    (79, 82), // Bishop
+    (85, 88), // Rook
+];
+
+/// `BonaPiece` offsets of the pieces on the board, for friend. Those of enemy are followed by 81.
+fn board_offset(pk: PieceKind) -> usize {
+    90 + 162
+        * match pk {
+            PieceKind::Pawn => 0,
+            PieceKind::Lance => 1,
+            PieceKind::Knight => 2,
+            PieceKind::Silver => 3,
+            PieceKind::Gold
+            | PieceKind::ProPawn
+            | PieceKind::ProLance
+            | PieceKind::ProKnight
+            | PieceKind::ProSilver => 4,
+            PieceKind::Bishop => 5,
+            PieceKind::ProBishop => 6,
+            PieceKind::Rook => 7,
+            PieceKind::ProRook => 8,
+            PieceKind::King => unreachable!(),
+        }
+}
+
+fn relative_index(sq: Square, perspective: Color) -> usize {
+    match perspective {
+        Color::Black => sq.array_index(),
+        Color::White => Square::NUM - 1 - sq.array_index(),
+    }
+}
+
+/// A piece on a square, or the `n`-th (0-origin) piece of a kind in hand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PieceAt {
+    Board(Square, Piece),
+    Hand(Piece, u8),
+}
+
+impl PieceAt {
+    /// Returns the `BonaPiece` seen from the perspective, or `None` for kings.
+    fn bona_piece(self, perspective: Color) -> Option<usize> {
+        match self {
+            PieceAt::Board(sq, p) => {
+                let (pk, c) = p.to_parts();
+                if pk == PieceKind::King {
+                    return None;
+                }
+                let offset = board_offset(pk) + if c == perspective { 0 } else { Square::NUM };
+                Some(offset + relative_index(sq, perspective))
+            }
+            PieceAt::Hand(p, n) => {
+                let (pk, c) = p.to_parts();
+                let index = Hand::all_hand_pieces().position(|hp| hp == pk)?;
+                let (friend, enemy) = HAND_OFFSETS[index];
+                Some(if c == perspective { friend } else { enemy } + usize::from(n))
+            }
+        }
+    }
+}
+
+/// Pieces changed by the last move.
+#[derive(Clone, Debug)]
+pub(crate) struct DirtyPiece {
+    removed: ArrayVec<PieceAt, 2>,
+    added: ArrayVec<PieceAt, 2>,
+}
+
+impl DirtyPiece {
+    /// `hand` is the hand of the player who made the move, after the move.
+    pub fn new(m: Move, moved: Piece, captured: Option<Piece>, hand: Hand) -> Self {
+        let mut removed = ArrayVec::new();
+        let mut added = ArrayVec::new();
+        match m {
+            Move::Normal { from, to, promote } => {
+                removed.push(PieceAt::Board(from, moved));
+                added.push(PieceAt::Board(
+                    to,
+                    if promote {
+                        moved.promote().unwrap_or(moved)
+                    } else {
+                        moved
+                    },
+                ));
+                if let Some(p) = captured {
+                    let pk = p.piece_kind();
+                    let pk = pk.unpromote().unwrap_or(pk);
+                    let count = hand.count(pk).unwrap_or_default();
+                    removed.push(PieceAt::Board(to, p));
+                    added.push(PieceAt::Hand(
+                        Piece::new(pk, moved.color()),
+                        count.saturating_sub(1),
+                    ));
+                }
+            }
+            Move::Drop { to, piece } => {
+                let count = hand.count(piece.piece_kind()).unwrap_or_default();
+                removed.push(PieceAt::Hand(piece, count));
+                added.push(PieceAt::Board(to, piece));
+            }
+        }
+        Self { removed, added }
+    }
+    fn king_moved(&self, c: Color) -> bool {
+        self.removed
+            .iter()
+            .any(|&pa| matches!(pa, PieceAt::Board(_, p) if p == Piece::new(PieceKind::King, c)))
+    }
+}
+
+/// Returns the index of the HalfKP feature, or `None` if the king of the perspective is not on the board.
+fn feature_index(pos: &Position, perspective: Color, bona_piece: usize) -> Option<usize> {
+    let king = relative_index(pos.king_position(perspective)?, perspective);
+    Some(king * FE_END + bona_piece)
+}
+
+/// Returns the active HalfKP features of the position seen from the perspective.
+pub fn active_features(pos: &Position, perspective: Color) -> ArrayVec<usize, 38> {
+    let mut features = ArrayVec::new();
+    if pos.king_position(perspective).is_none() {
+        return features;
+    }
+    for sq in pos.occupied_bitboard() {
+        let p = pos.piece_at(sq).expect("occupied square");
+        if let Some(bp) = PieceAt::Board(sq, p).bona_piece(perspective) {
+            features.extend(feature_index(pos, perspective, bp));
+        }
+    }
+    for c in Color::all() {
+        for pk in Hand::all_hand_pieces() {
+            for n in 0..pos.hand(c).count(pk).unwrap_or_default() {
+                if let Some(bp) = PieceAt::Hand(Piece::new(pk, c), n).bona_piece(perspective) {
+                    features.extend(feature_index(pos, perspective, bp));
+                }
+            }
+        }
+    }
+    features
+}
+
+/// Returns the HalfKP features `(removed, added)` by the last move, seen from the perspective.
+///
+/// Returns `None` if the features can't be updated incrementally:
+/// no moves have been made, or the king of the perspective has moved.
+pub fn changed_features(
+    pos: &Position,
+    perspective: Color,
+) -> Option<(ArrayVec<usize, 2>, ArrayVec<usize, 2>)> {
+    let dirty_piece = pos.dirty_piece()?;
+    if dirty_piece.king_moved(perspective) {
+        return None;
+    }
+    let indices = |pieces: &ArrayVec<PieceAt, 2>| -> Option<ArrayVec<usize, 2>> {
+        let mut av = ArrayVec::new();
+        for pa in pieces {
+            if let Some(bp) = pa.bona_piece(perspective) {
+                av.push(feature_index(pos, perspective, bp)?);
+            }
+        }
+        Some(av)
+    };
+    Some((indices(&dirty_piece.removed)?, indices(&dirty_piece.added)?))
+}
+
+/// Sums of the weights of the active features for each perspective, indexed by [`Color`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Accumulator<const N: usize> {
+    pub values: [[i16; N]; Color::NUM],
+}
+
+/// The first layer of NNUE, which transforms the HalfKP features into an [`Accumulator`].
+pub struct FeatureTransformer<const N: usize> {
+    biases: Vec<i16>,
+    weights: Vec<i16>,
+}
+
+impl<const N: usize> FeatureTransformer<N> {
+    /// # Panics
+    ///
+    /// Panics if `biases` doesn't have `N` values or `weights` doesn't have `HALFKP_DIMENSIONS * N` values.
+    pub fn new(biases: Vec<i16>, weights: Vec<i16>) -> Self {
+        assert_eq!(N, biases.len());
+        assert_eq!(HALFKP_DIMENSIONS * N, weights.len());
+        Self { biases, weights }
+    }
+    /// Computes the accumulator of the position from scratch.
+    pub fn refresh(&self, pos: &Position) -> Accumulator<N> {
+        let mut values = [[0; N]; Color::NUM];
+        for c in Color::all() {
+            self.refresh_perspective(pos, c, &mut values[c.array_index()]);
+        }
+        Accumulator { values }
+    }
+    /// Computes the accumulator of the position from the one before the last move,
+    /// refreshing the perspective whose king has moved.
+    pub fn update(&self, pos: &Position, prev: &Accumulator<N>) -> Accumulator<N> {
+        let mut values = prev.values;
+        for c in Color::all() {
+            let acc = &mut values[c.array_index()];
+            if let Some((removed, added)) = changed_features(pos, c) {
+                for index in removed {
+                    for (v, w) in acc.iter_mut().zip(self.weight(index)) {
+                        *v = v.wrapping_sub(*w);
+                    }
+                }
+                for index in added {
+                    for (v, w) in acc.iter_mut().zip(self.weight(index)) {
+                        *v = v.wrapping_add(*w);
+                    }
+                }
+            } else {
+                self.refresh_perspective(pos, c, acc);
+            }
+        }
+        Accumulator { values }
+    }
+    fn refresh_perspective(&self, pos: &Position, perspective: Color, acc: &mut [i16; N]) {
+        acc.copy_from_slice(&self.biases);
+        for index in active_features(pos, perspective) {
+            for (v, w) in acc.iter_mut().zip(self.weight(index)) {
+                *v = v.wrapping_add(*w);
+            }
+        }
+    }
+    fn weight(&self, index: usize) -> &[i16] {
+        &self.weights[index * N..(index + 1) * N]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use shogi_core::PartialPosition;
+    use shogi_usi_parser::FromUsi;
+
+    #[test]
+    fn startpos() {
+        let pos = Position::default();
+        for c in Color::all() {
+            let features = active_features(&pos, c);
+            assert_eq!(38, features.len());
+            assert!(features.iter().all(|&index| index < HALFKP_DIMENSIONS));
+        }
+        // black pawn on 7G from the black king on 5I (index 44)
+        assert!(active_features(&pos, Color::Black).contains(&(44 * FE_END + 90 + 60)));
+        // the same feature from the white king
+        assert!(active_features(&pos, Color::White).contains(&(44 * FE_END + 90 + 81 + 20)));
+        assert_eq!(None, changed_features(&pos, Color::Black));
+    }
+
+    #[test]
+    fn hands() {
+        let pos = Position::new(
+            PartialPosition::from_usi("sfen 4k4/9/9/9/9/9/9/9/4K4 b 2Pg 1")
+                .expect("failed to parse"),
+        );
+        let features = active_features(&pos, Color::Black);
+        assert_eq!(
+            vec![44 * FE_END + 1, 44 * FE_END + 2, 44 * FE_END + 74],
+            features.to_vec()
+        );
+        let features = active_features(&pos, Color::White);
+        assert_eq!(
+            vec![44 * FE_END + 20, 44 * FE_END + 21, 44 * FE_END + 69],
+            features.to_vec()
+        );
+    }
+
+    #[test]
+    fn changed() {
+        let mut pos = Position::default();
+        for m in [
+            Move::Normal {
+                from: Square::SQ_7G,
+                to: Square::SQ_7F,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_3C,
+                to: Square::SQ_3D,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_8H,
+                to: Square::SQ_2B,
+                promote: true,
+            },
+        ] {
+            pos.do_move(m);
+        }
+        // capture with promotion
+        let (removed, added) = changed_features(&pos, Color::Black).expect("no changes");
+        assert_eq!(2, removed.len());
+        assert_eq!(2, added.len());
+        assert!(added.contains(&(44 * FE_END + 79)));
+        // king moves
+        pos.do_move(Move::Normal {
+            from: Square::SQ_5A,
+            to: Square::SQ_4B,
+            promote: false,
+        });
+        assert_eq!(None, changed_features(&pos, Color::White));
+        assert!(changed_features(&pos, Color::Black).is_some());
+        // drop
+        pos.do_move(Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::B_B,
+        });
+        let (removed, added) = changed_features(&pos, Color::Black).expect("no changes");
+        assert_eq!(vec![44 * FE_END + 79], removed.to_vec());
+        assert_eq!(vec![44 * FE_END + 900 + 40], added.to_vec());
+    }
+
+    #[test]
+    fn incremental_update() {
+        const N: usize = 8;
+        let mut rng = StdRng::seed_from_u64(0);
+        let biases = (0..N).map(|_| rng.gen_range(-100..100)).collect();
+        let weights = (0..HALFKP_DIMENSIONS * N)
+            .map(|_| rng.gen_range(-100..100))
+            .collect();
+        let ft = FeatureTransformer::<N>::new(biases, weights);
+        let mut pos = Position::default();
+        let mut moves = Vec::new();
+        let mut stack = vec![ft.refresh(&pos)];
+        for _ in 0..200 {
+            let Some(&m) = pos.legal_moves().choose(&mut rng) else {
+                break;
+            };
+            pos.do_move(m);
+            moves.push(m);
+            let acc = ft.update(&pos, stack.last().unwrap());
+            assert_eq!(ft.refresh(&pos), acc);
+            stack.push(acc);
+        }
+        while let Some(m) = moves.pop() {
+            pos.undo_move(m);
+            stack.pop();
+            assert_eq!(&ft.refresh(&pos), stack.last().unwrap());
+        }
+    }
+}
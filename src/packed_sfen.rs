@@ -0,0 +1,367 @@
+//! [YaneuraOu](https://github.com/yaneurao/YaneuraOu)'s `PackedSfen` encoding of a position,
+//! and the `PackedSfenValue` records of its training data (`.bin`) files.
+use crate::bitstream::{BitReader, BitWriter};
+use crate::Position;
+use shogi_core::{Color, Move, PartialPosition, Piece, PieceKind, Square};
+use std::io::{self, Read, Write};
+
+/// Piece kinds in hand, in the order of YaneuraOu's `PieceType` (from `PAWN` = 1 to `GOLD` = 7).
+const PIECE_TYPES: [PieceKind; 7] = [
+    PieceKind::Pawn,
+    PieceKind::Lance,
+    PieceKind::Knight,
+    PieceKind::Silver,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+    PieceKind::Gold,
+];
+
+/// Huffman codes `(code, bits)` of the pieces on the board, indexed by YaneuraOu's `PieceType` without promotion.
+/// The first entry stands for an empty square.
+const BOARD_CODES: [(u32, usize); 8] = [
+    (0x00, 1), // Empty
+    (0x01, 2), // Pawn
+    (0x03, 4), // Lance
+    (0x0b, 4), // Knight
+    (0x07, 4), // Silver
+    (0x1f, 6), // Bishop
+    (0x3f, 6), // Rook
+    (0x0f, 5), // Gold
+];
+
+/// Huffman codes of the pieces in hand: the board codes without the leading "occupied" bit.
+const HAND_CODES: [(u32, usize); 8] = {
+    let mut codes = [(0, 0); 8];
+    let mut i = 1;
+    while i < 8 {
+        codes[i] = (BOARD_CODES[i].0 >> 1, BOARD_CODES[i].1 - 1);
+        i += 1;
+    }
+    codes
+};
+
+/// Returns YaneuraOu's `PieceType` of the piece kind without promotion.
+fn piece_type(pk: PieceKind) -> usize {
+    let raw = pk.unpromote().unwrap_or(pk);
+    PIECE_TYPES
+        .iter()
+        .position(|&t| t == raw)
+        .map_or(0, |i| i + 1)
+}
+
+/// A position encoded in 256 bits. It requires both kings on the board and all the other 38 pieces on the board or in hands.
+///
+/// Note that the ply is not a part of the encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PackedSfen(pub [u8; 32]);
+
+/// A 40-byte record of YaneuraOu's training data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PackedSfenValue {
+    pub sfen: PackedSfen,
+    /// Evaluation value from the side to move
+    pub score: i16,
+    /// Best move in YaneuraOu's 16-bit move format, see [`encode_move`]
+    pub mv: u16,
+    pub game_ply: u16,
+    /// Result of the game from the side to move: 1 for win, 0 for draw and -1 for loss
+    pub game_result: i8,
+}
+
+impl PackedSfenValue {
+    pub const SIZE: usize = 40;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let mut sfen = [0; 32];
+        sfen.copy_from_slice(&bytes[..32]);
+        Self {
+            sfen: PackedSfen(sfen),
+            score: i16::from_le_bytes([bytes[32], bytes[33]]),
+            mv: u16::from_le_bytes([bytes[34], bytes[35]]),
+            game_ply: u16::from_le_bytes([bytes[36], bytes[37]]),
+            game_result: bytes[38] as i8,
+        }
+    }
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..32].copy_from_slice(&self.sfen.0);
+        bytes[32..34].copy_from_slice(&self.score.to_le_bytes());
+        bytes[34..36].copy_from_slice(&self.mv.to_le_bytes());
+        bytes[36..38].copy_from_slice(&self.game_ply.to_le_bytes());
+        bytes[38] = self.game_result as u8;
+        bytes
+    }
+    /// Decodes the position of this record, with its `game_ply` as the ply.
+    pub fn position(&self) -> Option<Position> {
+        Position::from_packed_sfen(&self.sfen, self.game_ply.max(1))
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+/// Reads [`PackedSfenValue`]s from a `.bin` file until its end.
+pub struct PackedSfenValueReader<R> {
+    reader: R,
+}
+
+impl<R: Read> PackedSfenValueReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: Read> Iterator for PackedSfenValueReader<R> {
+    type Item = io::Result<PackedSfenValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0; PackedSfenValue::SIZE];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => Some(Ok(PackedSfenValue::from_bytes(&buf))),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Encodes a move into YaneuraOu's 16-bit format:
+/// destination in bits 0-6, source (or piece kind for drops) in bits 7-13,
+/// drop flag in bit 14 and promotion flag in bit 15.
+pub fn encode_move(m: Move) -> u16 {
+    match m {
+        Move::Normal { from, to, promote } => {
+            u16::from(to.index() - 1)
+                | (u16::from(from.index() - 1) << 7)
+                | (u16::from(promote) << 15)
+        }
+        Move::Drop { to, piece } => {
+            u16::from(to.index() - 1) | ((piece_type(piece.piece_kind()) as u16) << 7) | (1 << 14)
+        }
+    }
+}
+
+/// Decodes a move from YaneuraOu's 16-bit format. The color is needed to determine the dropped piece.
+pub fn decode_move(m16: u16, c: Color) -> Option<Move> {
+    let to = Square::from_u8((m16 & 0x7f) as u8 + 1)?;
+    let from = ((m16 >> 7) & 0x7f) as u8;
+    if m16 & (1 << 14) != 0 {
+        let pk = *PIECE_TYPES.get(usize::from(from).checked_sub(1)?)?;
+        Some(Move::Drop {
+            to,
+            piece: Piece::new(pk, c),
+        })
+    } else {
+        let from = Square::from_u8(from + 1)?;
+        if from == to {
+            return None;
+        }
+        Some(Move::Normal {
+            from,
+            to,
+            promote: m16 & (1 << 15) != 0,
+        })
+    }
+}
+
+impl Position {
+    /// Encodes the position into a [`PackedSfen`].
+    /// Returns `None` if the pieces can't be packed into 256 bits, e.g. a king or some other pieces are missing.
+    pub fn to_packed_sfen(&self) -> Option<PackedSfen> {
+        let mut writer = BitWriter::new();
+        writer.write_bit(self.side_to_move() == Color::White);
+        for c in Color::all() {
+            writer.write_bits(self.king_position(c)?.array_index() as u32, 7);
+        }
+        for sq in Square::all() {
+            match self.piece_at(sq) {
+                Some(p) if p.piece_kind() == PieceKind::King => {}
+                Some(p) => {
+                    let (pk, c) = p.to_parts();
+                    let (code, bits) = BOARD_CODES[piece_type(pk)];
+                    writer.write_bits(code, bits);
+                    if pk != PieceKind::Gold {
+                        writer.write_bit(pk.unpromote().is_some());
+                    }
+                    writer.write_bit(c == Color::White);
+                }
+                None => writer.write_bits(BOARD_CODES[0].0, BOARD_CODES[0].1),
+            }
+        }
+        for c in Color::all() {
+            for pk in PIECE_TYPES {
+                for _ in 0..self.hand(c).count(pk).unwrap_or_default() {
+                    let (code, bits) = HAND_CODES[piece_type(pk)];
+                    writer.write_bits(code, bits);
+                    if pk != PieceKind::Gold {
+                        writer.write_bit(false);
+                    }
+                    writer.write_bit(c == Color::White);
+                }
+            }
+        }
+        writer.finish().map(PackedSfen)
+    }
+    /// Decodes a [`PackedSfen`] with the given ply.
+    /// Returns `None` if the data is not a valid encoding.
+    pub fn from_packed_sfen(psfen: &PackedSfen, ply: u16) -> Option<Position> {
+        let mut partial = PartialPosition::empty();
+        let mut reader = BitReader::new(&psfen.0);
+        partial.side_to_move_set([Color::Black, Color::White][usize::from(reader.read_bit()?)]);
+        if !partial.ply_set(ply) {
+            return None;
+        }
+        let mut kings = [None; Color::NUM];
+        for c in Color::all() {
+            // YaneuraOu writes 81 for a missing king, which is not supported
+            let sq = Square::from_u8(reader.read_bits(7)? as u8 + 1)?;
+            partial.piece_set(sq, Some(Piece::new(PieceKind::King, c)));
+            kings[c.array_index()] = Some(sq);
+        }
+        if kings[0] == kings[1] {
+            return None;
+        }
+        for sq in Square::all() {
+            if kings.contains(&Some(sq)) {
+                continue;
+            }
+            let raw = match reader.read_huffman(&BOARD_CODES)? {
+                0 => continue,
+                i => PIECE_TYPES[i - 1],
+            };
+            let promote = raw != PieceKind::Gold && reader.read_bit()?;
+            let c = [Color::Black, Color::White][usize::from(reader.read_bit()?)];
+            let pk = if promote { raw.promote()? } else { raw };
+            partial.piece_set(sq, Some(Piece::new(pk, c)));
+        }
+        while !reader.is_end() {
+            let pk = PIECE_TYPES[reader.read_huffman(&HAND_CODES[1..])?];
+            if pk != PieceKind::Gold && reader.read_bit()? {
+                return None;
+            }
+            let c = [Color::Black, Color::White][usize::from(reader.read_bit()?)];
+            let hand = partial.hand_of_a_player_mut(c);
+            *hand = hand.added(pk)?;
+        }
+        Some(Position::new(partial))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_usi_parser::FromUsi;
+
+    #[test]
+    fn startpos() {
+        let pos = Position::default();
+        let psfen = pos.to_packed_sfen().expect("failed to pack");
+        let decoded = Position::from_packed_sfen(&psfen, 1).expect("failed to unpack");
+        assert!(Square::all().all(|sq| pos.piece_at(sq) == decoded.piece_at(sq)));
+        assert_eq!(pos.key(), decoded.key());
+        assert_eq!(Color::Black, decoded.side_to_move());
+        // turn bit and the black king on 5I (index 44)
+        assert_eq!(0b0101_1000, psfen.0[0]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for sfen in [
+            // from maximum moves
+            "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+            // promoted pieces
+            "sfen 4k3K/7+p1/8+p/1p5+p1/p1p5+p/1+p1p3+p1/+p3p3+p/1+p3p1+p1/+p5+p2 w 2R2B4G4S4N4L 1",
+            "sfen lnsgkg1nl/1r5s1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/7R1/LNSGKGSNL w Bb 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            let psfen = pos.to_packed_sfen().expect("failed to pack");
+            let decoded = Position::from_packed_sfen(&psfen, 1).expect("failed to unpack");
+            assert_eq!(pos.key(), decoded.key(), "{sfen}");
+            assert_eq!(pos.side_to_move(), decoded.side_to_move(), "{sfen}");
+        }
+    }
+
+    #[test]
+    fn missing_pieces() {
+        for sfen in [
+            "sfen 4k4/9/9/9/9/9/9/9/4K4 b 2R2B4G4S4N4L17P 1",
+            "sfen 9/9/9/9/9/9/9/9/9 b 2R2B4G4S4N4L18P 1",
+            // no black king
+            "sfen 4k4/9/9/9/9/9/9/9/9 b 2R2B4G4S4N4L18P 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            assert_eq!(None, pos.to_packed_sfen(), "{sfen}");
+        }
+        // the black king on 81, for a missing one
+        let mut psfen = Position::default()
+            .to_packed_sfen()
+            .expect("failed to pack");
+        psfen.0[0] = psfen.0[0] & 1 | (Square::NUM as u8) << 1;
+        assert!(Position::from_packed_sfen(&psfen, 1).is_none());
+    }
+
+    #[test]
+    fn moves() {
+        let mut pos = Position::default();
+        for m in [
+            Move::Normal {
+                from: Square::SQ_7G,
+                to: Square::SQ_7F,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_3C,
+                to: Square::SQ_3D,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_8H,
+                to: Square::SQ_2B,
+                promote: true,
+            },
+            Move::Normal {
+                from: Square::SQ_3A,
+                to: Square::SQ_2B,
+                promote: false,
+            },
+        ] {
+            pos.do_move(m);
+        }
+        for m in pos.legal_moves() {
+            let m16 = encode_move(m);
+            assert_eq!(Some(m), decode_move(m16, pos.side_to_move()));
+        }
+        let m = Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::B_B,
+        };
+        // BISHOP = 5 in YaneuraOu
+        assert_eq!((5 << 7) | (1 << 14) | 40, encode_move(m));
+        assert_eq!(None, decode_move(0, Color::Black));
+    }
+
+    #[test]
+    fn read_write() {
+        let pos = Position::default();
+        let value = PackedSfenValue {
+            sfen: pos.to_packed_sfen().expect("failed to pack"),
+            score: -123,
+            mv: encode_move(Move::Normal {
+                from: Square::SQ_7G,
+                to: Square::SQ_7F,
+                promote: false,
+            }),
+            game_ply: 1,
+            game_result: -1,
+        };
+        let mut buf = Vec::new();
+        value.write_to(&mut buf).expect("failed to write");
+        value.write_to(&mut buf).expect("failed to write");
+        assert_eq!(2 * PackedSfenValue::SIZE, buf.len());
+        let values = PackedSfenValueReader::new(buf.as_slice())
+            .collect::<io::Result<Vec<_>>>()
+            .expect("failed to read");
+        assert_eq!(vec![value, value], values);
+        let decoded = values[0].position().expect("failed to unpack");
+        assert_eq!(pos.key(), decoded.key());
+    }
+}
@@ -0,0 +1,256 @@
+//! Perft: counting the leaf nodes of the move tree, to validate the move generation.
+use crate::Position;
+use shogi_core::Move;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+/// Breakdown of the moves at the last ply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub promotions: u64,
+    pub drops: u64,
+    pub checks: u64,
+    /// Checks by another piece than the moved one.
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.captures += rhs.captures;
+        self.promotions += rhs.promotions;
+        self.drops += rhs.drops;
+        self.checks += rhs.checks;
+        self.discovered_checks += rhs.discovered_checks;
+        self.double_checks += rhs.double_checks;
+        self.checkmates += rhs.checkmates;
+    }
+}
+
+/// Returns the number of the leaf nodes at the depth.
+pub fn perft(pos: &mut Position, depth: usize) -> u64 {
+    match depth {
+        0 => 1,
+        1 => pos.legal_moves().len() as u64,
+        _ => pos
+            .legal_moves()
+            .into_iter()
+            .map(|m| {
+                pos.do_move(m);
+                let count = perft(pos, depth - 1);
+                pos.undo_move(m);
+                count
+            })
+            .sum(),
+    }
+}
+
+/// Returns the number of the leaf nodes at the depth, reusing the counts of the transpositions.
+pub fn perft_with_table(pos: &mut Position, depth: usize, table: &PerftTable) -> u64 {
+    if depth <= 1 {
+        return perft(pos, depth);
+    }
+    if let Some(count) = table.probe(pos.key(), depth) {
+        return count;
+    }
+    let count = pos
+        .legal_moves()
+        .into_iter()
+        .map(|m| {
+            pos.do_move(m);
+            let count = perft_with_table(pos, depth - 1, table);
+            pos.undo_move(m);
+            count
+        })
+        .sum();
+    table.store(pos.key(), depth, count);
+    count
+}
+
+/// Returns the breakdown of the moves at the depth.
+pub fn stats(pos: &mut Position, depth: usize) -> Stats {
+    let mut stats = Stats::default();
+    if depth == 0 {
+        stats.nodes = 1;
+        return stats;
+    }
+    for m in pos.legal_moves() {
+        if depth == 1 {
+            match m {
+                Move::Normal { to, promote, .. } => {
+                    stats.captures += u64::from(pos.piece_at(to).is_some());
+                    stats.promotions += u64::from(promote);
+                }
+                Move::Drop { .. } => stats.drops += 1,
+            }
+        }
+        pos.do_move(m);
+        if depth == 1 {
+            stats.nodes += 1;
+            if pos.in_check() {
+                let checkers = pos.checkers();
+                stats.checks += 1;
+                stats.discovered_checks += u64::from(checkers.into_iter().any(|sq| sq != m.to()));
+                stats.double_checks += u64::from(checkers.count() > 1);
+                stats.checkmates += u64::from(pos.legal_moves().is_empty());
+            }
+        } else {
+            stats += self::stats(pos, depth - 1);
+        }
+        pos.undo_move(m);
+    }
+    stats
+}
+
+/// Returns the number of the leaf nodes after each legal move, searched by the threads
+/// in parallel with the shared table if any.
+///
+/// # Panics
+///
+/// Panics if `depth` is 0, where no move is made.
+pub fn divide(
+    pos: &Position,
+    depth: usize,
+    threads: usize,
+    table: Option<&PerftTable>,
+) -> Vec<(Move, u64)> {
+    assert!(depth > 0, "divide at depth 0");
+    let moves = pos.legal_moves();
+    let counts = moves.iter().map(|_| AtomicU64::new(0)).collect::<Vec<_>>();
+    let next = AtomicUsize::new(0);
+    let worker = || {
+        let mut pos = pos.clone();
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(&m) = moves.get(i) else {
+                break;
+            };
+            pos.do_move(m);
+            let count = match table {
+                Some(table) => perft_with_table(&mut pos, depth - 1, table),
+                None => perft(&mut pos, depth - 1),
+            };
+            pos.undo_move(m);
+            counts[i].store(count, Ordering::Relaxed);
+        }
+    };
+    thread::scope(|s| {
+        for _ in 1..threads {
+            s.spawn(worker);
+        }
+        worker();
+    });
+    moves
+        .into_iter()
+        .zip(counts.into_iter().map(AtomicU64::into_inner))
+        .collect()
+}
+
+/// Table of the perft counts keyed by [`Position::key`] and the depth, shared between threads.
+///
+/// Each entry is a pair of `AtomicU64` with the key xor-ed with the data, so that an entry torn
+/// by concurrent writes is never mistaken for a valid one.
+pub struct PerftTable {
+    entries: Box<[[AtomicU64; 2]]>,
+}
+
+impl PerftTable {
+    /// Creates a table of the size in megabytes.
+    pub fn new(mb: usize) -> Self {
+        let len = (mb * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        Self {
+            entries: (0..len).map(|_| Default::default()).collect(),
+        }
+    }
+    pub fn probe(&self, key: u64, depth: usize) -> Option<u64> {
+        let [check, data] = self.entry(key);
+        let data = data.load(Ordering::Relaxed);
+        // an empty entry has depth 0, which is never probed
+        (data & 0xff == depth as u64 && check.load(Ordering::Relaxed) ^ data == key)
+            .then_some(data >> 8)
+    }
+    /// Stores the count, always replacing the entry.
+    pub fn store(&self, key: u64, depth: usize, count: u64) {
+        let [check, entry] = self.entry(key);
+        let data = count << 8 | depth as u64 & 0xff;
+        check.store(key ^ data, Ordering::Relaxed);
+        entry.store(data, Ordering::Relaxed);
+    }
+    fn entry(&self, key: u64) -> &[AtomicU64; 2] {
+        let index = (u128::from(key) * self.entries.len() as u128) >> 64;
+        &self.entries[index as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MOVEGEN_POSITIONS};
+
+    #[test]
+    fn default_position() {
+        let mut pos = Position::default();
+        assert_eq!(1, perft(&mut pos, 0));
+        assert_eq!(30, perft(&mut pos, 1));
+        assert_eq!(900, perft(&mut pos, 2));
+        assert_eq!(25470, perft(&mut pos, 3));
+
+        let table = PerftTable::new(1);
+        assert_eq!(25470, perft_with_table(&mut pos, 3, &table));
+        assert_eq!(Some(25470), table.probe(pos.key(), 3));
+        assert_eq!(None, table.probe(pos.key(), 2));
+        assert_eq!(719731, perft_with_table(&mut pos, 4, &table));
+    }
+
+    #[test]
+    fn breakdown() {
+        // https://qiita.com/ak11/items/8bd5f2bb0f5b014143c8
+        let mut pos = Position::default();
+        assert_eq!(
+            Stats {
+                nodes: 25470,
+                captures: 59,
+                promotions: 30,
+                drops: 0,
+                checks: 48,
+                discovered_checks: 0,
+                double_checks: 0,
+                checkmates: 0,
+            },
+            stats(&mut pos, 3)
+        );
+    }
+
+    #[test]
+    fn maximum_moves() {
+        let mut pos = position(MOVEGEN_POSITIONS[1]);
+        let stats = stats(&mut pos, 1);
+        assert_eq!(593, stats.nodes);
+        assert_eq!(
+            (0, 0),
+            (
+                stats.discovered_checks + stats.double_checks,
+                stats.captures
+            )
+        );
+        assert!(stats.checks > 0 && stats.checkmates > 0);
+
+        let divided = divide(&pos, 2, 4, None);
+        assert_eq!(593, divided.len());
+        assert_eq!(105677, divided.iter().map(|(_, count)| count).sum::<u64>());
+        let table = PerftTable::new(1);
+        assert_eq!(divided, divide(&pos, 2, 4, Some(&table)));
+        assert_eq!(divided, divide(&pos, 2, 1, Some(&table)));
+    }
+
+    #[test]
+    #[should_panic(expected = "divide at depth 0")]
+    fn divide_at_depth_zero() {
+        divide(&Position::default(), 0, 1, None);
+    }
+}
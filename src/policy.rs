@@ -0,0 +1,185 @@
+//! Policy labels of moves, in the same layout as [dlshogi](https://github.com/TadaoYamaoka/DeepLearningShogi).
+//!
+//! A label is `direction * 81 + to`, where `to` is the index of the destination square
+//! from the viewpoint of the side to move (rotated by 180 degrees if white is to move), and `direction` is one of:
+//!
+//! | direction | description                                                                       |
+//! |-----------|-----------------------------------------------------------------------------------|
+//! | 0..10     | `UP`, `UP_LEFT`, `UP_RIGHT`, `LEFT`, `RIGHT`, `DOWN`, `DOWN_LEFT`, `DOWN_RIGHT`, `UP2_LEFT`, `UP2_RIGHT` |
+//! | 10..20    | the same directions with promotion                                                |
+//! | 20..27    | drops of pawn, lance, knight, silver, gold, bishop and rook                       |
+use crate::Position;
+use shogi_core::{Color, Move, PieceKind, Square};
+
+const MOVE_DIRECTION_NUM: usize = 20;
+
+/// Piece kinds in the order of the drop directions.
+const HAND_PIECES: [PieceKind; 7] = [
+    PieceKind::Pawn,
+    PieceKind::Lance,
+    PieceKind::Knight,
+    PieceKind::Silver,
+    PieceKind::Gold,
+    PieceKind::Bishop,
+    PieceKind::Rook,
+];
+
+/// Number of all the labels: 27 directions for each of 81 squares.
+pub const MOVE_LABEL_NUM: usize = (MOVE_DIRECTION_NUM + HAND_PIECES.len()) * Square::NUM;
+
+fn relative_index(sq: Square, c: Color) -> usize {
+    match c {
+        Color::Black => sq.array_index(),
+        Color::White => Square::NUM - 1 - sq.array_index(),
+    }
+}
+
+/// Returns the label of the move of the side to move.
+pub fn move_to_label(pos: &Position, m: Move) -> usize {
+    let c = pos.side_to_move();
+    let to = relative_index(m.to(), c);
+    let direction = match m {
+        Move::Normal { from, promote, .. } => {
+            let from = relative_index(from, c);
+            let (from_x, from_y) = ((from / 9) as i8, (from % 9) as i8);
+            let (to_x, to_y) = ((to / 9) as i8, (to % 9) as i8);
+            let (dir_x, dir_y) = (from_x - to_x, to_y - from_y);
+            #[rustfmt::skip]
+            let direction = match (dir_x.signum(), dir_y) {
+                (-1, -2) if dir_x == -1 => 8, // UP2_LEFT
+                ( 1, -2) if dir_x ==  1 => 9, // UP2_RIGHT
+                ( 0, y) if y < 0        => 0, // UP
+                (-1, y) if y < 0        => 1, // UP_LEFT
+                ( 1, y) if y < 0        => 2, // UP_RIGHT
+                (-1, 0)                 => 3, // LEFT
+                ( 1, 0)                 => 4, // RIGHT
+                ( 0, _)                 => 5, // DOWN
+                (-1, _)                 => 6, // DOWN_LEFT
+                _                       => 7, // DOWN_RIGHT
+            };
+            if promote {
+                direction + MOVE_DIRECTION_NUM / 2
+            } else {
+                direction
+            }
+        }
+        Move::Drop { piece, .. } => {
+            MOVE_DIRECTION_NUM
+                + HAND_PIECES
+                    .iter()
+                    .position(|&pk| pk == piece.piece_kind())
+                    .unwrap_or_default()
+        }
+    };
+    direction * Square::NUM + to
+}
+
+/// Returns the legal move which has the label, if any.
+pub fn label_to_move(pos: &Position, label: usize) -> Option<Move> {
+    pos.legal_moves()
+        .into_iter()
+        .find(|&m| move_to_label(pos, m) == label)
+}
+
+/// Fills the mask of the labels: 1 for the labels of the legal moves and 0 for the others.
+///
+/// # Panics
+///
+/// Panics if `mask` is shorter than [`MOVE_LABEL_NUM`].
+pub fn legal_move_mask<T: Copy + From<u8>>(pos: &Position, mask: &mut [T]) {
+    let mask = &mut mask[..MOVE_LABEL_NUM];
+    mask.fill(T::from(0));
+    for m in pos.legal_moves() {
+        mask[move_to_label(pos, m)] = T::from(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::{PartialPosition, Piece};
+    use shogi_usi_parser::FromUsi;
+    use std::collections::HashSet;
+
+    #[test]
+    fn labels() {
+        let mut pos = Position::default();
+        // 7F is (6, 5)
+        let m = Move::Normal {
+            from: Square::SQ_7G,
+            to: Square::SQ_7F,
+            promote: false,
+        };
+        assert_eq!(59, move_to_label(&pos, m));
+        pos.do_move(m);
+        // 3D is (2, 3), rotated to (6, 5)
+        let m = Move::Normal {
+            from: Square::SQ_3C,
+            to: Square::SQ_3D,
+            promote: false,
+        };
+        assert_eq!(59, move_to_label(&pos, m));
+        pos.do_move(m);
+        // UP_RIGHT with promotion
+        let m = Move::Normal {
+            from: Square::SQ_8H,
+            to: Square::SQ_2B,
+            promote: true,
+        };
+        assert_eq!(
+            12 * 81 + Square::SQ_2B.array_index(),
+            move_to_label(&pos, m)
+        );
+        pos.do_move(m);
+        // white's DOWN_RIGHT, rotated to UP_LEFT
+        let m = Move::Normal {
+            from: Square::SQ_3A,
+            to: Square::SQ_2B,
+            promote: false,
+        };
+        assert_eq!(
+            81 + 80 - Square::SQ_2B.array_index(),
+            move_to_label(&pos, m)
+        );
+        pos.do_move(m);
+        // drop of bishop
+        let m = Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::B_B,
+        };
+        assert_eq!(25 * 81 + 40, move_to_label(&pos, m));
+        // UP2_LEFT
+        let m = Move::Normal {
+            from: Square::SQ_2I,
+            to: Square::SQ_3G,
+            promote: false,
+        };
+        assert_eq!(8 * 81 + Square::SQ_3G.array_index(), move_to_label(&pos, m));
+    }
+
+    #[test]
+    fn bijection() {
+        for sfen in [
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+            "sfen 8K/1r7/9/9/9/9/7l1/6ppp/3+B3nk w RBGSNLP3g3n14p 1",
+        ] {
+            let pos = Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"));
+            let moves = pos.legal_moves();
+            let labels = moves
+                .iter()
+                .map(|&m| move_to_label(&pos, m))
+                .collect::<HashSet<_>>();
+            assert_eq!(moves.len(), labels.len(), "{sfen}");
+            for m in moves {
+                let label = move_to_label(&pos, m);
+                assert!(label < MOVE_LABEL_NUM);
+                assert_eq!(Some(m), label_to_move(&pos, label), "{sfen}");
+            }
+            let mut mask = vec![0.0_f32; MOVE_LABEL_NUM];
+            legal_move_mask(&pos, &mut mask);
+            assert_eq!(labels.len() as f32, mask.iter().sum::<f32>());
+            assert!(labels.iter().all(|&label| mask[label] == 1.0));
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use crate::bitboard::Bitboard;
+#[cfg(feature = "nnue")]
+use crate::nnue::DirtyPiece;
 use crate::tables::{ATTACK_TABLE, BETWEEN_TABLE};
 use crate::zobrist::{Key, ZOBRIST_TABLE};
 use shogi_core::{Color, Hand, Move, Piece, PieceKind, Square};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// Represents a state of the game with history. This provides the ability to do and undo moves.
 #[derive(Debug, Clone)]
@@ -14,27 +18,16 @@ pub struct Position {
 impl Position {
     pub fn new(partial: shogi_core::PartialPosition) -> Position {
         let inner = PartialPosition::from(partial);
-        let mut keys = (Key::ZERO, Key::ZERO);
-        for sq in Square::all() {
-            if let Some(p) = inner.board[sq.array_index()] {
-                keys.0 ^= ZOBRIST_TABLE.board(sq, p);
-            }
-        }
-        for c in Color::all() {
-            for pk in Hand::all_hand_pieces() {
-                if let Some(num) = inner.hands[c.array_index()].count(pk) {
-                    for i in 0..num {
-                        keys.1 ^= ZOBRIST_TABLE.hand(c, pk, i);
-                    }
-                }
-            }
-        }
+        let keys = inner.keys();
         let checkers = AttackInfo::calculate_checkers(&inner);
         let state = State {
             keys,
             captured: None,
             last_moved: None,
+            last_move: None,
             attack_info: AttackInfo::new(checkers, &inner),
+            #[cfg(feature = "nnue")]
+            dirty_piece: None,
         };
         Self {
             inner,
@@ -69,6 +62,43 @@ impl Position {
     pub fn in_check(&self) -> bool {
         !self.checkers().is_empty()
     }
+    #[inline(always)]
+    pub fn last_move(&self) -> Option<Move> {
+        self.state().last_move
+    }
+    /// Returns the moves made from the initial position, skipping the null moves.
+    pub fn moves(&self) -> Vec<Move> {
+        self.states.iter().filter_map(|s| s.last_move).collect()
+    }
+    /// Returns whether a null move has been made since the initial position,
+    /// in which case [`Position::moves`] doesn't reproduce the position.
+    pub fn has_null_move(&self) -> bool {
+        self.states[1..].iter().any(|s| s.last_move.is_none())
+    }
+    /// Returns the current position without history.
+    pub fn to_partial_position(&self) -> shogi_core::PartialPosition {
+        let mut partial = shogi_core::PartialPosition::empty();
+        for sq in Square::all() {
+            partial.piece_set(sq, self.piece_at(sq));
+        }
+        for c in Color::all() {
+            *partial.hand_of_a_player_mut(c) = self.hand(c);
+        }
+        partial.side_to_move_set(self.side_to_move());
+        let _ = partial.ply_set(self.ply());
+        partial
+    }
+    /// Returns the position before the first move, including null moves.
+    pub fn initial_position(&self) -> shogi_core::PartialPosition {
+        let mut pos = self.clone();
+        for state in self.states[1..].iter().rev() {
+            match state.last_move {
+                Some(m) => pos.undo_move(m),
+                None => pos.undo_null_move(),
+            }
+        }
+        pos.to_partial_position()
+    }
     pub fn is_check_move(&self, m: Move) -> bool {
         match m {
             Move::Normal { from, to, promote } => {
@@ -166,12 +196,20 @@ impl Position {
         };
         self.inner.ply += 1;
         keys.0 ^= Key::COLOR;
+        #[cfg(feature = "nnue")]
+        let dirty_piece =
+            last_moved.map(|p| DirtyPiece::new(m, p, captured, self.inner.hand_of_a_player(c)));
         self.states.push(State {
             keys,
             captured,
             last_moved,
+            last_move: Some(m),
             attack_info: AttackInfo::new(checkers, &self.inner),
+            #[cfg(feature = "nnue")]
+            dirty_piece,
         });
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("do_move");
     }
     pub fn undo_move(&mut self, m: Move) {
         let c = self.side_to_move().flip();
@@ -205,6 +243,128 @@ impl Position {
         self.inner.side = c;
         self.inner.ply -= 1;
         self.states.pop();
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("undo_move");
+    }
+    /// Passes the turn to the opponent without moving, for null move pruning.
+    /// This must not be called while in check.
+    pub fn do_null_move(&mut self) {
+        debug_assert!(!self.in_check());
+        let mut keys = self.state().keys;
+        keys.0 ^= Key::COLOR;
+        self.inner.side = self.inner.side.flip();
+        self.inner.ply += 1;
+        self.states.push(State {
+            keys,
+            captured: None,
+            last_moved: None,
+            last_move: None,
+            attack_info: AttackInfo::new(Bitboard::empty(), &self.inner),
+            #[cfg(feature = "nnue")]
+            dirty_piece: None,
+        });
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("do_null_move");
+    }
+    pub fn undo_null_move(&mut self) {
+        self.inner.side = self.inner.side.flip();
+        self.inner.ply -= 1;
+        self.states.pop();
+        #[cfg(feature = "debug-invariants")]
+        self.assert_invariants("undo_null_move");
+    }
+    /// Returns the result for the side to move if the current position has appeared before,
+    /// with the same side to move, since the initial position or the last null move.
+    ///
+    /// A repetition by continuous checks is a loss for the side giving them, and a draw otherwise.
+    pub fn repetition(&self) -> Option<Repetition> {
+        let n = self.states.len() - 1;
+        let keys = self.state().keys;
+        let in_check = |i: usize| !self.states[i].attack_info.checkers().is_empty();
+        // whether the side to move has been in check, and whether the opponent has been in check
+        let (mut checked, mut checking) = (true, true);
+        for k in 1..=n {
+            if self.states[n + 1 - k].last_move.is_none() {
+                break;
+            }
+            if k % 2 == 1 {
+                checking &= in_check(n - k);
+                continue;
+            }
+            checked &= in_check(n + 2 - k);
+            let prev = self.states[n - k].keys;
+            if prev.0.value() == keys.0.value() && prev.1.value() == keys.1.value() {
+                return Some(if checked {
+                    Repetition::Win
+                } else if checking {
+                    Repetition::Loss
+                } else {
+                    Repetition::Draw
+                });
+            }
+        }
+        None
+    }
+    /// Compares the positions including the ply and the history, which are ignored by `==`.
+    pub fn eq_with_history(&self, other: &Position) -> bool {
+        self == other
+            && self.ply() == other.ply()
+            && self.states.len() == other.states.len()
+            && self.states.iter().zip(&other.states).all(|(s, o)| {
+                s.last_move == o.last_move
+                    && s.keys.0.value() == o.keys.0.value()
+                    && s.keys.1.value() == o.keys.1.value()
+            })
+    }
+    /// Recomputes the bitboards, the zobrist hashes and the attack information from the board
+    /// and the hands, and returns the first mismatch with the incrementally updated ones.
+    pub fn validate(&self) -> Result<(), Inconsistency> {
+        let same = |a: Bitboard, b: Bitboard| (a ^ b).is_empty();
+        let expected = PartialPosition::from(self.to_partial_position());
+        for c in Color::all() {
+            if !same(expected.player_bb[c.array_index()], self.player_bitboard(c)) {
+                return Err(Inconsistency::PlayerBitboard(c));
+            }
+        }
+        for pk in PieceKind::all() {
+            if !same(
+                expected.piece_bb[pk.array_index()],
+                self.piece_kind_bitboard(pk),
+            ) {
+                return Err(Inconsistency::PieceBitboard(pk));
+            }
+        }
+        let (keys, expected_keys) = (self.state().keys, expected.keys());
+        if keys.0.value() != expected_keys.0.value() {
+            return Err(Inconsistency::BoardKey);
+        }
+        if keys.1.value() != expected_keys.1.value() {
+            return Err(Inconsistency::HandKey);
+        }
+        let checkers = AttackInfo::calculate_checkers(&expected);
+        let attack_info = &self.state().attack_info;
+        if !same(checkers, attack_info.checkers) {
+            return Err(Inconsistency::Checkers);
+        }
+        let expected = AttackInfo::new(checkers, &expected);
+        for c in Color::all() {
+            if !same(expected.pinned(c), attack_info.pinned(c)) {
+                return Err(Inconsistency::Pinned(c));
+            }
+        }
+        for pk in PieceKind::all() {
+            let i = pk.array_index();
+            if !same(expected.checkables[i], attack_info.checkables[i]) {
+                return Err(Inconsistency::Checkables(pk));
+            }
+        }
+        Ok(())
+    }
+    #[cfg(feature = "debug-invariants")]
+    fn assert_invariants(&self, action: &str) {
+        if let Err(err) = self.validate() {
+            panic!("{err} after {action} at ply {}", self.ply());
+        }
     }
     #[inline(always)]
     pub(crate) fn player_bitboard(&self, c: Color) -> Bitboard {
@@ -235,6 +395,11 @@ impl Position {
     pub(crate) fn last_moved(&self) -> Option<Piece> {
         self.state().last_moved
     }
+    #[cfg(feature = "nnue")]
+    #[inline(always)]
+    pub(crate) fn dirty_piece(&self) -> Option<&DirtyPiece> {
+        self.state().dirty_piece.as_ref()
+    }
     #[inline(always)]
     pub(crate) fn checkers(&self) -> Bitboard {
         self.state().attack_info.checkers()
@@ -253,6 +418,69 @@ impl Position {
     }
 }
 
+/// Result of a repetition for the side to move, returned by [`Position::repetition`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repetition {
+    Draw,
+    /// The opponent has been giving continuous checks.
+    Win,
+    /// The side to move has been giving continuous checks.
+    Loss,
+}
+
+/// Mismatch between the incrementally updated data of a position and the recomputed ones,
+/// returned by [`Position::validate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The bitboard of the player doesn't match the board.
+    PlayerBitboard(Color),
+    /// The bitboard of the piece kind doesn't match the board.
+    PieceBitboard(PieceKind),
+    /// The zobrist hash of the board and the side to move
+    BoardKey,
+    /// The zobrist hash of the hands
+    HandKey,
+    Checkers,
+    /// The pieces pinned to the king of the color
+    Pinned(Color),
+    /// The squares where the piece kind gives check
+    Checkables(PieceKind),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::PlayerBitboard(c) => write!(f, "inconsistent bitboard of {c:?}"),
+            Inconsistency::PieceBitboard(pk) => write!(f, "inconsistent bitboard of {pk:?}"),
+            Inconsistency::BoardKey => write!(f, "inconsistent board key"),
+            Inconsistency::HandKey => write!(f, "inconsistent hand key"),
+            Inconsistency::Checkers => write!(f, "inconsistent checkers"),
+            Inconsistency::Pinned(c) => write!(f, "inconsistent pinned pieces of {c:?}"),
+            Inconsistency::Checkables(pk) => write!(f, "inconsistent checkable squares of {pk:?}"),
+        }
+    }
+}
+
+impl std::error::Error for Inconsistency {}
+
+/// Compares the board, the pieces in hand and the side to move, ignoring the ply and the history.
+/// Use [`Position::eq_with_history`] to compare them too.
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.side == other.inner.side
+            && self.inner.hands == other.inner.hands
+            && self.inner.board == other.inner.board
+    }
+}
+
+impl Eq for Position {}
+
+impl Hash for Position {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.key());
+    }
+}
+
 impl Default for Position {
     fn default() -> Self {
         Self::new(shogi_core::PartialPosition::startpos())
@@ -304,6 +532,28 @@ impl PartialPosition {
             .into_iter()
             .next()
     }
+    /// Calculates the zobrist hashes from scratch.
+    fn keys(&self) -> (Key, Key) {
+        let mut keys = (Key::ZERO, Key::ZERO);
+        for sq in Square::all() {
+            if let Some(p) = self.board[sq.array_index()] {
+                keys.0 ^= ZOBRIST_TABLE.board(sq, p);
+            }
+        }
+        if self.side == Color::White {
+            keys.0 ^= Key::COLOR;
+        }
+        for c in Color::all() {
+            for pk in Hand::all_hand_pieces() {
+                if let Some(num) = self.hands[c.array_index()].count(pk) {
+                    for i in 0..num {
+                        keys.1 ^= ZOBRIST_TABLE.hand(c, pk, i);
+                    }
+                }
+            }
+        }
+        keys
+    }
 }
 
 impl From<shogi_core::PartialPosition> for PartialPosition {
@@ -342,7 +592,12 @@ struct State {
     captured: Option<Piece>,
     /// Last moved piece
     last_moved: Option<Piece>,
+    /// Last move
+    last_move: Option<Move>,
     attack_info: AttackInfo,
+    /// Pieces changed by the last move, for updating NNUE features
+    #[cfg(feature = "nnue")]
+    dirty_piece: Option<DirtyPiece>,
 }
 
 #[derive(Debug, Clone)]
@@ -547,6 +802,9 @@ mod tests {
         assert_eq!(Color::White, pos.side_to_move());
         assert_eq!(6, pos.ply());
         assert_eq!(true, pos.in_check());
+        assert_eq!(Some(moves[4]), pos.last_move());
+        assert_eq!(moves.to_vec(), pos.moves());
+        assert_eq!(PartialPosition::startpos(), pos.initial_position());
         // revert to default position
         for &m in moves.iter().rev() {
             pos.undo_move(m);
@@ -556,6 +814,226 @@ mod tests {
         assert_eq!(Color::Black, pos.side_to_move());
         assert_eq!(1, pos.ply());
         assert_eq!(false, pos.in_check());
+        assert_eq!(None, pos.last_move());
+        assert!(pos.moves().is_empty());
+    }
+
+    #[test]
+    fn key_of_white_to_move() {
+        let mut pos = Position::default();
+        pos.do_move(Move::from_usi("7g7f").expect("failed to parse"));
+        let parsed = Position::new(
+            PartialPosition::from_usi(
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+            )
+            .expect("failed to parse"),
+        );
+        assert_eq!(pos.key(), parsed.key());
+        assert_eq!(pos.keys(), parsed.keys());
+    }
+
+    #[test]
+    fn null_move() {
+        let mut pos = Position::default();
+        let key = pos.key();
+        pos.do_null_move();
+        assert_eq!(Color::White, pos.side_to_move());
+        assert_eq!(2, pos.ply());
+        assert_ne!(key, pos.key());
+        assert_eq!(None, pos.last_move());
+        assert_eq!(30, pos.legal_moves().len());
+        pos.undo_null_move();
+        assert_eq!(Color::Black, pos.side_to_move());
+        assert_eq!(1, pos.ply());
+        assert_eq!(key, pos.key());
+
+        // in the history
+        pos.do_move(Move::from_usi("7g7f").expect("failed to parse"));
+        pos.do_null_move();
+        pos.do_move(Move::from_usi("2g2f").expect("failed to parse"));
+        assert!(pos.has_null_move());
+        assert_eq!(2, pos.moves().len());
+        assert_eq!(PartialPosition::startpos(), pos.initial_position());
+        assert!(!Position::default().has_null_move());
+    }
+
+    #[test]
+    fn repetition() {
+        let moves = [
+            Move::Normal {
+                from: Square::SQ_5I,
+                to: Square::SQ_5H,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_5A,
+                to: Square::SQ_5B,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_5H,
+                to: Square::SQ_5I,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_5B,
+                to: Square::SQ_5A,
+                promote: false,
+            },
+        ];
+        let mut pos = Position::default();
+        for m in moves {
+            assert_eq!(None, pos.repetition());
+            pos.do_move(m);
+        }
+        assert_eq!(Some(Repetition::Draw), pos.repetition());
+        // a null move breaks the history
+        pos.do_null_move();
+        pos.do_move(moves[1]);
+        pos.do_null_move();
+        pos.do_move(moves[3]);
+        assert_eq!(None, pos.repetition());
+
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  *  *  *  *  *  *  *
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9+OU *  *  *  *  *  * +HI *
+        // P-00AL
+        // +
+        let mut pos = Position::new(
+            PartialPosition::from_usi("sfen 8k/9/9/9/9/9/9/9/K6R1 b 2b4g4s4n4l18p 1")
+                .expect("failed to parse"),
+        );
+        for (from, to) in [
+            (Square::SQ_2I, Square::SQ_1I),
+            (Square::SQ_1A, Square::SQ_2A),
+            (Square::SQ_1I, Square::SQ_2I),
+            (Square::SQ_2A, Square::SQ_1A),
+        ] {
+            assert_eq!(None, pos.repetition());
+            pos.do_move(Move::Normal {
+                from,
+                to,
+                promote: false,
+            });
+        }
+        // black has been giving continuous checks
+        assert_eq!(Some(Repetition::Loss), pos.repetition());
+        pos.do_move(Move::Normal {
+            from: Square::SQ_2I,
+            to: Square::SQ_1I,
+            promote: false,
+        });
+        assert_eq!(Some(Repetition::Win), pos.repetition());
+    }
+
+    #[test]
+    fn eq_and_hash() {
+        use std::collections::HashSet;
+
+        let moves = |moves: &[&str]| {
+            let mut pos = Position::default();
+            for m in moves {
+                pos.do_move(Move::from_usi(m).expect("failed to parse"));
+            }
+            pos
+        };
+        // transpositions
+        let pos0 = moves(&["7g7f", "3c3d", "2g2f"]);
+        let pos1 = moves(&["2g2f", "3c3d", "7g7f"]);
+        assert_eq!(pos0, pos1);
+        assert!(!pos0.eq_with_history(&pos1));
+        assert!(pos0.eq_with_history(&pos0.clone()));
+        // the same position with another ply
+        let pos2 = moves(&["7g7f", "3c3d", "2g2f", "4a3b", "3i4h", "3b4a", "4h3i"]);
+        assert_eq!(pos0, pos2);
+        assert_ne!(pos0.ply(), pos2.ply());
+        // the same board with the other side to move
+        let mut partial = pos0.to_partial_position();
+        partial.side_to_move_set(Color::Black);
+        let pos3 = Position::new(partial);
+        assert_ne!(pos0, pos3);
+        // from the parsed position
+        assert_eq!(pos0, Position::new(pos0.to_partial_position()));
+
+        let set = [pos0, pos1, pos2, pos3, Position::default()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        assert_eq!(3, set.len());
+    }
+
+    #[test]
+    fn validate() {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut pos = Position::default();
+        assert_eq!(Ok(()), pos.validate());
+        let mut moves = Vec::new();
+        while let Some(&m) = pos.legal_moves().choose(&mut rng) {
+            pos.do_move(m);
+            assert_eq!(Ok(()), pos.validate(), "{m:?}");
+            moves.push(m);
+            if moves.len() == 200 {
+                break;
+            }
+        }
+        for m in moves.into_iter().rev() {
+            pos.undo_move(m);
+            assert_eq!(Ok(()), pos.validate(), "{m:?}");
+        }
+        // the same keys as the position reached by the moves
+        let mut pos = Position::default();
+        let m = pos.legal_moves()[0];
+        pos.do_move(m);
+        assert_eq!(pos.key(), Position::new(pos.to_partial_position()).key());
+
+        #[cfg(not(feature = "debug-invariants"))]
+        {
+            let mut pos = Position::default();
+            misuse_undo_move(&mut pos);
+            assert!(pos.validate().is_err());
+        }
+
+        let mut pos = Position::default();
+        pos.inner.xor_piece(Square::SQ_5E, Piece::B_P);
+        assert_eq!(
+            Err(Inconsistency::PlayerBitboard(Color::Black)),
+            pos.validate()
+        );
+        let mut pos = Position::default();
+        pos.inner.hands[0] = pos.inner.hands[0].added(PieceKind::Pawn).unwrap();
+        assert_eq!(Err(Inconsistency::HandKey), pos.validate());
+        let mut pos = Position::default();
+        pos.inner.side = Color::White;
+        assert_eq!(Err(Inconsistency::BoardKey), pos.validate());
+    }
+
+    #[cfg(feature = "debug-invariants")]
+    #[test]
+    #[should_panic(expected = "after undo_move")]
+    fn debug_invariants() {
+        misuse_undo_move(&mut Position::default());
+    }
+
+    /// Undoes a move with another one.
+    fn misuse_undo_move(pos: &mut Position) {
+        pos.do_move(Move::Normal {
+            from: Square::SQ_7G,
+            to: Square::SQ_7F,
+            promote: false,
+        });
+        pos.undo_move(Move::Normal {
+            from: Square::SQ_6G,
+            to: Square::SQ_7F,
+            promote: false,
+        });
     }
 
     #[test]
@@ -0,0 +1,260 @@
+//! Generator of random legal positions, for stress tests and diverse training data.
+//!
+//! A generated position has all the 40 pieces, no two unpromoted pawns of a color on a file (二歩),
+//! no piece which can't move any more, and the side not to move is not in check.
+use crate::Position;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use shogi_core::{Color, PartialPosition, Piece, PieceKind, Square};
+use std::ops::RangeInclusive;
+
+/// Number of the pieces other than the kings.
+const PIECES_NUM: usize = 38;
+
+/// Numbers of each piece kind in hand order, other than the kings.
+const PIECE_COUNTS: [(PieceKind, usize); 7] = [
+    (PieceKind::Pawn, 18),
+    (PieceKind::Lance, 4),
+    (PieceKind::Knight, 4),
+    (PieceKind::Silver, 4),
+    (PieceKind::Gold, 4),
+    (PieceKind::Bishop, 2),
+    (PieceKind::Rook, 2),
+];
+
+/// Options of the random positions, used by [`Generator::generate`].
+#[derive(Clone, Debug)]
+pub struct Generator {
+    on_board: RangeInclusive<usize>,
+    endgame: bool,
+    in_check: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            on_board: 0..=PIECES_NUM,
+            endgame: false,
+            in_check: false,
+        }
+    }
+}
+
+impl Generator {
+    /// Sets the range of the number of the pieces on the board other than the kings,
+    /// and the rest are in hand. It's clamped to 38.
+    pub fn on_board(mut self, on_board: RangeInclusive<usize>) -> Self {
+        self.on_board = on_board;
+        self
+    }
+    /// Biases towards endgames, where more pieces are promoted and gathered around the kings.
+    pub fn endgame(mut self, endgame: bool) -> Self {
+        self.endgame = endgame;
+        self
+    }
+    /// Generates only the positions where the side to move is in check, by at most two pieces.
+    ///
+    /// # Panics
+    ///
+    /// [`Generator::generate`] panics if no piece can be on the board.
+    pub fn in_check(mut self, in_check: bool) -> Self {
+        self.in_check = in_check;
+        self
+    }
+    /// Returns a random legal position, retrying until the options are satisfied.
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> Position {
+        assert!(
+            !self.in_check || *self.on_board.end() > 0,
+            "no piece to give check"
+        );
+        loop {
+            let partial = self.partial_position(rng);
+            let mut opponent = partial.clone();
+            opponent.side_to_move_set(partial.side_to_move().flip());
+            if Position::new(opponent).in_check() {
+                continue;
+            }
+            let pos = Position::new(partial);
+            let checkers = pos.checkers().count();
+            if checkers <= 2 && (!self.in_check || checkers > 0) {
+                return pos;
+            }
+        }
+    }
+    fn partial_position<R: Rng>(&self, rng: &mut R) -> PartialPosition {
+        let mut partial = PartialPosition::empty();
+        let black_king = *Square::all().collect::<Vec<_>>().choose(rng).unwrap();
+        let white_king = *Square::all()
+            .filter(|&sq| distance(sq, black_king) > 1)
+            .collect::<Vec<_>>()
+            .choose(rng)
+            .unwrap();
+        partial.piece_set(black_king, Some(Piece::B_K));
+        partial.piece_set(white_king, Some(Piece::W_K));
+
+        let mut pieces = PIECE_COUNTS
+            .iter()
+            .flat_map(|&(pk, n)| (0..n).map(move |_| pk))
+            .collect::<Vec<_>>();
+        pieces.shuffle(rng);
+        let start = (*self.on_board.start()).min(PIECES_NUM);
+        let end = (*self.on_board.end()).min(PIECES_NUM);
+        let on_board = rng.gen_range(start..=end.max(start));
+        let promotion_rate = if self.endgame { 0.4 } else { 0.1 };
+        for (i, pk) in pieces.into_iter().enumerate() {
+            let c = if rng.gen() {
+                Color::Black
+            } else {
+                Color::White
+            };
+            let promote = pk.promote().is_some() && rng.gen_bool(promotion_rate);
+            // the other color if no square is left, for pawns on every file
+            let (piece, mut squares) = [c, c.flip()]
+                .into_iter()
+                .map(|c| {
+                    let piece = Piece::new(pk, c);
+                    let piece = if promote {
+                        piece.promote().unwrap_or(piece)
+                    } else {
+                        piece
+                    };
+                    let squares = Square::all()
+                        .filter(|&sq| can_place(&partial, sq, piece))
+                        .collect::<Vec<_>>();
+                    (piece, squares)
+                })
+                .find(|(_, squares)| !squares.is_empty())
+                .unwrap_or((Piece::new(pk, c), Vec::new()));
+            if self.endgame && rng.gen() {
+                let king = if rng.gen() { black_king } else { white_king };
+                let near = squares
+                    .iter()
+                    .copied()
+                    .filter(|&sq| distance(sq, king) <= 2)
+                    .collect::<Vec<_>>();
+                if !near.is_empty() {
+                    squares = near;
+                }
+            }
+            match squares.choose(rng) {
+                Some(&sq) if i < on_board => partial.piece_set(sq, Some(piece)),
+                _ => {
+                    let hand = partial.hand_of_a_player_mut(piece.color());
+                    *hand = hand.added(pk).expect("too many pieces in hand");
+                }
+            }
+        }
+        partial.side_to_move_set(if rng.gen() {
+            Color::Black
+        } else {
+            Color::White
+        });
+        partial
+    }
+}
+
+/// Returns whether the piece can be placed on the empty square.
+fn can_place(partial: &PartialPosition, sq: Square, p: Piece) -> bool {
+    let c = p.color();
+    partial.piece_at(sq).is_none()
+        && match p.piece_kind() {
+            PieceKind::Pawn => {
+                sq.relative_rank(c) > 1
+                    && (1..=9).all(|rank| {
+                        Square::new(sq.file(), rank).and_then(|sq| partial.piece_at(sq)) != Some(p)
+                    })
+            }
+            PieceKind::Lance => sq.relative_rank(c) > 1,
+            PieceKind::Knight => sq.relative_rank(c) > 2,
+            _ => true,
+        }
+}
+
+/// Returns the Chebyshev distance of the squares.
+fn distance(sq0: Square, sq1: Square) -> u8 {
+    sq0.file()
+        .abs_diff(sq1.file())
+        .max(sq0.rank().abs_diff(sq1.rank()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use shogi_core::Hand;
+
+    fn assert_legal(pos: &Position) {
+        let sfen = pos.to_partial_position().to_sfen_owned();
+        assert_eq!(Ok(()), pos.validate(), "{sfen}");
+        let mut counts = [0; PieceKind::NUM];
+        for sq in Square::all() {
+            if let Some(p) = pos.piece_at(sq) {
+                let pk = p.piece_kind();
+                counts[pk.unpromote().unwrap_or(pk).array_index()] += 1;
+                assert!(can_place(&PartialPosition::empty(), sq, p), "{sfen}");
+            }
+        }
+        for c in Color::all() {
+            for pk in Hand::all_hand_pieces() {
+                counts[pk.array_index()] += usize::from(pos.hand(c).count(pk).unwrap_or_default());
+            }
+            for file in 1..=9 {
+                let pawns = (1..=9)
+                    .filter(|&rank| {
+                        pos.piece_at(Square::new(file, rank).unwrap())
+                            == Some(Piece::new(PieceKind::Pawn, c))
+                    })
+                    .count();
+                assert!(pawns <= 1, "{sfen}");
+            }
+        }
+        for (pk, n) in PIECE_COUNTS {
+            assert_eq!(n, counts[pk.array_index()], "{sfen}");
+        }
+        assert_eq!(2, counts[PieceKind::King.array_index()], "{sfen}");
+        let mut opponent = pos.to_partial_position();
+        opponent.side_to_move_set(pos.side_to_move().flip());
+        assert!(!Position::new(opponent).in_check(), "{sfen}");
+    }
+
+    fn on_board(pos: &Position) -> usize {
+        Square::all()
+            .filter(|&sq| pos.piece_at(sq).is_some())
+            .count()
+            - 2
+    }
+
+    #[test]
+    fn generate() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = Generator::default();
+        for _ in 0..200 {
+            assert_legal(&generator.generate(&mut rng));
+        }
+        // reproducible with the seed
+        let pos = generator.generate(&mut StdRng::seed_from_u64(1));
+        assert_eq!(pos, generator.generate(&mut StdRng::seed_from_u64(1)));
+    }
+
+    #[test]
+    fn options() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = Generator::default().on_board(10..=12).endgame(true);
+        for _ in 0..100 {
+            let pos = generator.generate(&mut rng);
+            assert_legal(&pos);
+            assert!((10..=12).contains(&on_board(&pos)));
+        }
+        let generator = Generator::default().on_board(0..=0);
+        for _ in 0..10 {
+            assert_eq!(0, on_board(&generator.generate(&mut rng)));
+        }
+        let generator = Generator::default().in_check(true);
+        for _ in 0..100 {
+            let pos = generator.generate(&mut rng);
+            assert_legal(&pos);
+            assert!(pos.in_check());
+        }
+    }
+}
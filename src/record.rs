@@ -0,0 +1,212 @@
+//! Game records, independent of the file formats.
+use crate::Position;
+use shogi_core::{Move, PartialPosition};
+use std::fmt;
+use std::time::Duration;
+
+/// A game record: the initial position, the moves with their variations, and the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub black_name: Option<String>,
+    pub white_name: Option<String>,
+    /// Other headers in the order of appearance. The keys depend on the file format.
+    pub headers: Vec<(String, String)>,
+    pub initial: PartialPosition,
+    /// Comments before the first move.
+    pub comments: Vec<String>,
+    /// The main line.
+    pub moves: Vec<RecordMove>,
+}
+
+impl Record {
+    /// Creates a record of the moves played in the position,
+    /// or `None` if a null move has been made, which can't be recorded.
+    pub fn from_position(pos: &Position) -> Option<Self> {
+        if pos.has_null_move() {
+            return None;
+        }
+        Some(Self {
+            initial: pos.initial_position(),
+            moves: pos
+                .moves()
+                .into_iter()
+                .map(|m| RecordMove::new(Action::Move(m)))
+                .collect(),
+            ..Default::default()
+        })
+    }
+    /// Returns the position after the moves of the main line, or `None` if any of them is illegal.
+    pub fn to_position(&self) -> Option<Position> {
+        let mut pos = Position::new(self.initial.clone());
+        for m in self.moves.iter().filter_map(|rm| rm.action.as_move()) {
+            if !pos.legal_moves().contains(&m) {
+                return None;
+            }
+            pos.do_move(m);
+        }
+        Some(pos)
+    }
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Self {
+            black_name: None,
+            white_name: None,
+            headers: Vec::new(),
+            initial: PartialPosition::startpos(),
+            comments: Vec::new(),
+            moves: Vec::new(),
+        }
+    }
+}
+
+/// A move in a game record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordMove {
+    pub action: Action,
+    /// Time spent for the move.
+    pub time: Option<Duration>,
+    pub comments: Vec<String>,
+    /// Alternative lines which start from this move instead.
+    pub variations: Vec<Vec<RecordMove>>,
+}
+
+impl RecordMove {
+    pub fn new(action: Action) -> Self {
+        Self {
+            action,
+            time: None,
+            comments: Vec::new(),
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Move(Move),
+    /// Ends the line.
+    Special(SpecialMove),
+}
+
+impl Action {
+    pub fn as_move(&self) -> Option<Move> {
+        match *self {
+            Action::Move(m) => Some(m),
+            Action::Special(_) => None,
+        }
+    }
+}
+
+/// Actions other than moves, which end the game (or the line).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialMove {
+    /// The side to move resigns.
+    Resign,
+    Interrupt,
+    /// Fourfold repetition.
+    Repetition,
+    /// The side to move runs out of time.
+    TimeUp,
+    /// The previous move was illegal.
+    IllegalMove,
+    /// The player of the color lost by an illegal action.
+    IllegalAction(shogi_core::Color),
+    /// Draw by impasse.
+    Jishogi,
+    /// The side to move declares the win by entering king.
+    EnteringKing,
+    Draw,
+    Matta,
+    /// The side to move is checkmated.
+    Mate,
+    /// No checkmate exists (in tsume problems).
+    NoMate,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidHeader,
+    InvalidPosition,
+    InvalidMove,
+    /// The move is well-formed, but not legal in the position.
+    IllegalMove,
+    InvalidVariation,
+}
+
+/// An error while parsing a game record, with the line number (1-origin).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.kind {
+            ParseErrorKind::InvalidHeader => "invalid header",
+            ParseErrorKind::InvalidPosition => "invalid position",
+            ParseErrorKind::InvalidMove => "invalid move",
+            ParseErrorKind::IllegalMove => "illegal move",
+            ParseErrorKind::InvalidVariation => "invalid variation",
+        };
+        write!(f, "{description} at line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::{Piece, Square};
+
+    #[test]
+    fn from_position() {
+        let mut pos = Position::default();
+        let moves = [
+            Move::Normal {
+                from: Square::SQ_7G,
+                to: Square::SQ_7F,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_3C,
+                to: Square::SQ_3D,
+                promote: false,
+            },
+            Move::Normal {
+                from: Square::SQ_8H,
+                to: Square::SQ_2B,
+                promote: true,
+            },
+        ];
+        for m in moves {
+            pos.do_move(m);
+        }
+        let record = Record::from_position(&pos).expect("null move");
+        assert_eq!(PartialPosition::startpos(), record.initial);
+        assert_eq!(
+            moves.to_vec(),
+            record
+                .moves
+                .iter()
+                .filter_map(|rm| rm.action.as_move())
+                .collect::<Vec<_>>()
+        );
+        let replayed = record.to_position().expect("failed to replay");
+        assert_eq!(pos.to_partial_position(), replayed.to_partial_position());
+        let mut null = pos.clone();
+        null.do_null_move();
+        assert_eq!(None, Record::from_position(&null));
+
+        let mut record = record;
+        record.moves.push(RecordMove::new(Action::Move(Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::W_B,
+        })));
+        assert!(record.to_position().is_none());
+    }
+}
@@ -0,0 +1,265 @@
+//! Naive square-by-square move generator, as the reference to test [`Position::legal_moves`].
+//!
+//! The moves are generated from the steps and slides of each piece on the board, and a move is
+//! legal if no piece of the opponent can capture the king after it. It's slow but simple enough
+//! to be checked by reading.
+use crate::Position;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use shogi_core::{Color, Move, Piece, PieceKind, Square};
+
+const ORTHOGONALS: [(i8, i8); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+const DIAGONALS: [(i8, i8); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+const GOLD: [(i8, i8); 6] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (0, 1)];
+const SILVER: [(i8, i8); 5] = [(-1, -1), (0, -1), (1, -1), (-1, 1), (1, 1)];
+const KNIGHT: [(i8, i8); 2] = [(-1, -2), (1, -2)];
+
+/// (file, rank) deltas
+type Directions = &'static [(i8, i8)];
+
+/// Returns the directions of the single steps and the slides of the piece kind, for black.
+/// Negative rank deltas are forward.
+fn directions(pk: PieceKind) -> (Directions, Directions) {
+    match pk {
+        PieceKind::Pawn => (&[(0, -1)], &[]),
+        PieceKind::Lance => (&[], &[(0, -1)]),
+        PieceKind::Knight => (&KNIGHT, &[]),
+        PieceKind::Silver => (&SILVER, &[]),
+        PieceKind::Gold
+        | PieceKind::ProPawn
+        | PieceKind::ProLance
+        | PieceKind::ProKnight
+        | PieceKind::ProSilver => (&GOLD, &[]),
+        PieceKind::Bishop => (&[], &DIAGONALS),
+        PieceKind::Rook => (&[], &ORTHOGONALS),
+        PieceKind::King => (
+            &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+            &[],
+        ),
+        PieceKind::ProBishop => (&ORTHOGONALS, &DIAGONALS),
+        PieceKind::ProRook => (&DIAGONALS, &ORTHOGONALS),
+    }
+}
+
+/// Returns the squares attacked by the piece on the square.
+fn attacks(pos: &Position, from: Square, p: Piece) -> Vec<Square> {
+    let sign = if p.color() == Color::Black { 1 } else { -1 };
+    let shift = |sq: Square, (df, dr): (i8, i8)| {
+        let file = sq.file() as i8 + df;
+        let rank = sq.rank() as i8 + dr * sign;
+        if (1..=9).contains(&file) && (1..=9).contains(&rank) {
+            Square::new(file as u8, rank as u8)
+        } else {
+            None
+        }
+    };
+    let (steps, slides) = directions(p.piece_kind());
+    let mut squares = steps
+        .iter()
+        .filter_map(|&d| shift(from, d))
+        .collect::<Vec<_>>();
+    for &d in slides {
+        let mut sq = from;
+        while let Some(to) = shift(sq, d) {
+            squares.push(to);
+            if pos.piece_at(to).is_some() {
+                break;
+            }
+            sq = to;
+        }
+    }
+    squares
+}
+
+/// Returns whether any piece of the color attacks the square.
+fn is_attacked(pos: &Position, sq: Square, by: Color) -> bool {
+    Square::all().any(|from| {
+        pos.piece_at(from)
+            .is_some_and(|p| p.color() == by && attacks(pos, from, p).contains(&sq))
+    })
+}
+
+/// Returns whether the king of the color is attacked.
+fn is_king_attacked(pos: &Position, c: Color) -> bool {
+    Square::all()
+        .find(|&sq| pos.piece_at(sq) == Some(Piece::new(PieceKind::King, c)))
+        .is_some_and(|sq| is_attacked(pos, sq, c.flip()))
+}
+
+/// Returns whether the piece can't move any more on the square.
+fn is_dead_end(pk: PieceKind, sq: Square, c: Color) -> bool {
+    match pk {
+        PieceKind::Pawn | PieceKind::Lance => sq.relative_rank(c) == 1,
+        PieceKind::Knight => sq.relative_rank(c) <= 2,
+        _ => false,
+    }
+}
+
+/// Returns all the legal moves of the position.
+pub fn legal_moves(pos: &Position) -> Vec<Move> {
+    generate(pos, true)
+}
+
+fn generate(pos: &Position, pawn_drop_mate: bool) -> Vec<Move> {
+    let c = pos.side_to_move();
+    let mut moves = Vec::new();
+    for from in Square::all() {
+        let Some(p) = pos.piece_at(from).filter(|p| p.color() == c) else {
+            continue;
+        };
+        let pk = p.piece_kind();
+        for to in attacks(pos, from, p) {
+            if pos.piece_at(to).is_some_and(|q| q.color() == c) {
+                continue;
+            }
+            let zone = from.relative_rank(c) <= 3 || to.relative_rank(c) <= 3;
+            if pk.promote().is_some() && zone {
+                moves.push(Move::Normal {
+                    from,
+                    to,
+                    promote: true,
+                });
+            }
+            if !is_dead_end(pk, to, c) {
+                moves.push(Move::Normal {
+                    from,
+                    to,
+                    promote: false,
+                });
+            }
+        }
+    }
+    for pk in shogi_core::Hand::all_hand_pieces() {
+        if pos.hand(c).count(pk).unwrap_or_default() == 0 {
+            continue;
+        }
+        let piece = Piece::new(pk, c);
+        for to in Square::all() {
+            if pos.piece_at(to).is_some() || is_dead_end(pk, to, c) {
+                continue;
+            }
+            // two pawns on a file (二歩)
+            if pk == PieceKind::Pawn
+                && Square::all().any(|sq| sq.file() == to.file() && pos.piece_at(sq) == Some(piece))
+            {
+                continue;
+            }
+            moves.push(Move::Drop { to, piece });
+        }
+    }
+    let mut pos = pos.clone();
+    moves.retain(|&m| {
+        pos.do_move(m);
+        let mut legal = !is_king_attacked(&pos, c);
+        // checkmate by a pawn drop (打ち歩詰め)
+        if legal && pawn_drop_mate {
+            if let Move::Drop { piece, .. } = m {
+                // a pawn drop can't evade the check by a pawn, so it's never examined again
+                legal = !(piece.piece_kind() == PieceKind::Pawn
+                    && is_king_attacked(&pos, c.flip())
+                    && generate(&pos, false).is_empty());
+            }
+        }
+        pos.undo_move(m);
+        legal
+    });
+    moves
+}
+
+/// Disagreement between [`legal_moves`] and [`Position::legal_moves`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub sfen: String,
+    /// Moves generated only by the reference
+    pub missing: Vec<Move>,
+    /// Moves generated only by [`Position::legal_moves`]
+    pub unexpected: Vec<Move>,
+}
+
+/// Compares the moves of the reference with [`Position::legal_moves`].
+pub fn compare(pos: &Position) -> Result<(), Mismatch> {
+    let expected = legal_moves(pos);
+    let actual = pos.legal_moves();
+    let missing = expected
+        .iter()
+        .filter(|m| !actual.contains(m))
+        .copied()
+        .collect::<Vec<_>>();
+    let unexpected = actual
+        .iter()
+        .filter(|m| !expected.contains(m))
+        .copied()
+        .collect::<Vec<_>>();
+    // no duplicates
+    if missing.is_empty() && unexpected.is_empty() && expected.len() == actual.len() {
+        Ok(())
+    } else {
+        Err(Mismatch {
+            sfen: pos.to_partial_position().to_sfen_owned(),
+            missing,
+            unexpected,
+        })
+    }
+}
+
+/// Compares the moves in every position of random games from the position,
+/// and returns the number of the compared positions.
+pub fn random_playouts<R: Rng>(
+    pos: &Position,
+    rng: &mut R,
+    games: usize,
+    max_ply: usize,
+) -> Result<u64, Mismatch> {
+    let mut count = 0;
+    for _ in 0..games {
+        let mut pos = pos.clone();
+        for _ in 0..max_ply {
+            compare(&pos)?;
+            count += 1;
+            let Some(&m) = pos.legal_moves().choose(rng) else {
+                break;
+            };
+            pos.do_move(m);
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MOVEGEN_POSITIONS};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn positions() {
+        for sfen in MOVEGEN_POSITIONS {
+            let pos = position(sfen);
+            assert_eq!(Ok(()), compare(&pos));
+        }
+        assert_eq!(
+            593,
+            legal_moves(&position(
+                "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1"
+            ))
+            .len()
+        );
+    }
+
+    #[test]
+    fn playouts() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let result = random_playouts(&Position::default(), &mut rng, 20, 256);
+        assert!(matches!(result, Ok(count) if count > 1000), "{result:?}");
+    }
+}
@@ -0,0 +1,683 @@
+//! Alpha-beta search with a pluggable evaluation function.
+//!
+//! [`Searcher`] runs iterative deepening PVS with aspiration windows, quiescence search,
+//! null move pruning, late move reductions and check extensions.
+use crate::tt::{Bound, TranspositionTable};
+use crate::{Position, Repetition};
+use shogi_core::{Color, Hand, Move, PieceKind, Square};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Score of a checkmate at the root. A mate in `n` plies is scored `MATE - n`.
+pub const MATE: i32 = 32000;
+/// Scores beyond this are mate scores.
+pub const MATE_IN_MAX_PLY: i32 = MATE - MAX_PLY as i32;
+pub const INFINITE: i32 = MATE + 1;
+/// Maximum depth of the search, including extensions and quiescence search.
+pub const MAX_PLY: usize = 128;
+
+const ASPIRATION_DELTA: i32 = 64;
+/// Quiescence search looks for all the evasions only in this many plies,
+/// since interposing drops and recaptures can continue for long.
+const QUIESCENCE_EVASION_PLY: usize = 4;
+
+/// Evaluation function used by [`Searcher`].
+pub trait Evaluate {
+    /// Returns the score of the position in centipawns, from the side to move's point of view.
+    /// The returned value must be within `(-MATE_IN_MAX_PLY, MATE_IN_MAX_PLY)`.
+    fn evaluate(&mut self, pos: &Position) -> i32;
+}
+
+/// Evaluates the position by the material balance.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaterialEvaluator;
+
+impl MaterialEvaluator {
+    /// Piece values in centipawns, indexed by `PieceKind::array_index`.
+    pub const PIECE_VALUES: [i32; PieceKind::NUM] = [
+        90, 315, 405, 495, 540, 855, 990, 0, 540, 540, 540, 540, 945, 1395,
+    ];
+}
+
+impl Evaluate for MaterialEvaluator {
+    fn evaluate(&mut self, pos: &Position) -> i32 {
+        let c = pos.side_to_move();
+        let mut score = 0;
+        for sq in Square::all() {
+            if let Some(p) = pos.piece_at(sq) {
+                let value = Self::PIECE_VALUES[p.piece_kind().array_index()];
+                score += if p.color() == c { value } else { -value };
+            }
+        }
+        for pk in Hand::all_hand_pieces() {
+            let value = Self::PIECE_VALUES[pk.array_index()];
+            let count = |c: Color| i32::from(pos.hand(c).count(pk).unwrap_or_default());
+            score += value * (count(c) - count(c.flip()));
+        }
+        score
+    }
+}
+
+/// Conditions to stop the search. The search runs until one of them is met.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    /// Flag to stop the search from another thread.
+    pub stop: Arc<AtomicBool>,
+}
+
+/// Result of the search, reported for each completed iteration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    /// `None` if there are no legal moves.
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub depth: u32,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    /// Returns the number of plies to the checkmate, negative if the side to move is mated.
+    pub fn mate_ply(&self) -> Option<i32> {
+        if self.score >= MATE_IN_MAX_PLY {
+            Some(MATE - self.score)
+        } else if self.score <= -MATE_IN_MAX_PLY {
+            Some(-MATE - self.score)
+        } else {
+            None
+        }
+    }
+}
+
+/// Searcher which keeps the transposition table and the move ordering heuristics between searches.
+pub struct Searcher<E> {
+    evaluator: E,
+    tt: Arc<TranspositionTable>,
+    nodes: u64,
+    start: Instant,
+    limits: Limits,
+    stopped: bool,
+    root_depth: usize,
+    /// Triangular table of the principal variations, indexed by ply
+    pv: Vec<Vec<Move>>,
+    /// Principal variation of the last iteration, tried first in the next iteration
+    prev_pv: Vec<Move>,
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    /// Scores of the quiet moves which caused cutoffs, indexed by the moved piece and the destination
+    history: Vec<[i32; Square::NUM]>,
+}
+
+impl<E: Evaluate> Searcher<E> {
+    /// Creates a searcher with the default size of transposition table.
+    pub fn new(evaluator: E) -> Self {
+        Self::with_table(evaluator, Arc::new(TranspositionTable::default()))
+    }
+    /// Creates a searcher using the transposition table, which may be shared with other searchers.
+    pub fn with_table(evaluator: E, tt: Arc<TranspositionTable>) -> Self {
+        Self {
+            evaluator,
+            tt,
+            nodes: 0,
+            start: Instant::now(),
+            limits: Limits::default(),
+            stopped: false,
+            root_depth: 0,
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            prev_pv: Vec::new(),
+            killers: [[None; 2]; MAX_PLY],
+            history: vec![[0; Square::NUM]; PieceKind::NUM * Color::NUM],
+        }
+    }
+    pub fn evaluator(&self) -> &E {
+        &self.evaluator
+    }
+    pub fn evaluator_mut(&mut self) -> &mut E {
+        &mut self.evaluator
+    }
+    pub fn table(&self) -> &Arc<TranspositionTable> {
+        &self.tt
+    }
+    /// Forgets the transposition table and the move ordering heuristics, for a new game.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.killers = [[None; 2]; MAX_PLY];
+        self.history.iter_mut().for_each(|h| h.fill(0));
+    }
+    pub fn search(&mut self, pos: &Position, limits: &Limits) -> SearchResult {
+        self.search_with(pos, limits, |_| {})
+    }
+    /// Searches the position, calling `on_iteration` with the result of each completed iteration.
+    pub fn search_with<F>(
+        &mut self,
+        pos: &Position,
+        limits: &Limits,
+        on_iteration: F,
+    ) -> SearchResult
+    where
+        F: FnMut(&SearchResult),
+    {
+        self.tt.new_search();
+        self.run(pos, limits, 0, on_iteration)
+    }
+    /// Runs iterative deepening from `1 + depth_offset`, without starting a new generation of
+    /// the transposition table, so that helper threads can join the search.
+    pub(crate) fn run<F>(
+        &mut self,
+        pos: &Position,
+        limits: &Limits,
+        depth_offset: u32,
+        mut on_iteration: F,
+    ) -> SearchResult
+    where
+        F: FnMut(&SearchResult),
+    {
+        let mut pos = pos.clone();
+        self.nodes = 0;
+        self.start = Instant::now();
+        self.limits = limits.clone();
+        self.stopped = false;
+        self.prev_pv.clear();
+        self.killers = [[None; 2]; MAX_PLY];
+        let moves = pos.legal_moves();
+        let mut result = SearchResult {
+            best_move: moves.first().copied(),
+            score: if moves.is_empty() { -MATE } else { 0 },
+            pv: Vec::new(),
+            depth: 0,
+            nodes: 0,
+        };
+        if moves.is_empty() {
+            return result;
+        }
+        let max_depth = limits.depth.unwrap_or(u32::MAX).min(MAX_PLY as u32 - 1);
+        for depth in (1 + depth_offset).min(max_depth)..=max_depth {
+            if self.limits.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            self.root_depth = depth as usize;
+            let mut delta = ASPIRATION_DELTA;
+            let (mut alpha, mut beta) = if depth >= 4 && result.mate_ply().is_none() {
+                (result.score - delta, result.score + delta)
+            } else {
+                (-INFINITE, INFINITE)
+            };
+            let score = loop {
+                let score = self.search_node(&mut pos, depth as i32, alpha, beta, 0, false);
+                if self.stopped {
+                    break score;
+                }
+                if score <= alpha {
+                    alpha = (score - delta).max(-INFINITE);
+                } else if score >= beta {
+                    beta = (score + delta).min(INFINITE);
+                } else {
+                    break score;
+                }
+                delta *= 2;
+            };
+            if self.stopped {
+                break;
+            }
+            self.prev_pv = self.pv[0].clone();
+            result = SearchResult {
+                best_move: self.pv[0].first().copied(),
+                score,
+                pv: self.pv[0].clone(),
+                depth,
+                nodes: self.nodes,
+            };
+            on_iteration(&result);
+            // no need to search deeper for the shortest mate
+            if result
+                .mate_ply()
+                .is_some_and(|ply| ply.unsigned_abs() <= depth)
+            {
+                break;
+            }
+        }
+        result.nodes = self.nodes;
+        result
+    }
+    fn should_stop(&mut self) -> bool {
+        if !self.stopped && (self.nodes.is_multiple_of(1024) || self.limits.nodes.is_some()) {
+            self.stopped = self.limits.stop.load(Ordering::Relaxed)
+                || self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes)
+                || self
+                    .limits
+                    .time
+                    .is_some_and(|time| self.start.elapsed() >= time);
+        }
+        self.stopped
+    }
+    fn search_node(
+        &mut self,
+        pos: &mut Position,
+        depth: i32,
+        mut alpha: i32,
+        mut beta: i32,
+        ply: usize,
+        null_allowed: bool,
+    ) -> i32 {
+        if depth <= 0 {
+            return self.quiesce(pos, alpha, beta, ply, 0);
+        }
+        self.pv[ply].clear();
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        if ply > 0 {
+            match pos.repetition() {
+                Some(Repetition::Draw) => return 0,
+                // path dependent, so never reported as a mate
+                Some(Repetition::Win) => return MATE_IN_MAX_PLY - 1,
+                Some(Repetition::Loss) => return -MATE_IN_MAX_PLY + 1,
+                None => {}
+            }
+            // mate distance pruning
+            alpha = alpha.max(-MATE + ply as i32);
+            beta = beta.min(MATE - ply as i32 - 1);
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluator.evaluate(pos);
+        }
+        let in_check = pos.in_check();
+        let pv_node = beta - alpha > 1;
+        let key = pos.key();
+        let tt_entry = self.tt.probe(key);
+        let tt_move = tt_entry.and_then(|entry| entry.best_move(pos.side_to_move()));
+        if let Some(entry) = tt_entry.filter(|entry| !pv_node && i32::from(entry.depth) >= depth) {
+            let score = score_from_tt(entry.score.into(), ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+        if null_allowed
+            && !pv_node
+            && !in_check
+            && depth >= 3
+            && beta.abs() < MATE_IN_MAX_PLY
+            && self.evaluator.evaluate(pos) >= beta
+        {
+            let reduction = 2 + depth / 4;
+            pos.do_null_move();
+            let score =
+                -self.search_node(pos, depth - 1 - reduction, -beta, -beta + 1, ply + 1, false);
+            pos.undo_null_move();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score.min(MATE_IN_MAX_PLY - 1);
+            }
+        }
+        let mut moves = pos.legal_moves();
+        if moves.is_empty() {
+            return -MATE + ply as i32;
+        }
+        self.order(pos, &mut moves, ply, tt_move);
+        let original_alpha = alpha;
+        let (mut best, mut best_move) = (-INFINITE, None);
+        for (i, &m) in moves.iter().enumerate() {
+            let quiet = !is_tactical(pos, m);
+            let gives_check = pos.is_check_move(m);
+            // extend checks, but not beyond twice the iteration depth
+            let new_depth = depth - 1 + i32::from(gives_check && ply < 2 * self.root_depth);
+            pos.do_move(m);
+            self.tt.prefetch(pos.key());
+            let score = if i == 0 {
+                -self.search_node(pos, new_depth, -beta, -alpha, ply + 1, true)
+            } else {
+                let reduction = if depth >= 3 && i >= 3 && quiet && !in_check && !gives_check {
+                    1 + i32::from(i >= 8 && !pv_node)
+                } else {
+                    0
+                };
+                let mut score = -self.search_node(
+                    pos,
+                    new_depth - reduction,
+                    -alpha - 1,
+                    -alpha,
+                    ply + 1,
+                    true,
+                );
+                if score > alpha && reduction > 0 {
+                    score = -self.search_node(pos, new_depth, -alpha - 1, -alpha, ply + 1, true);
+                }
+                if score > alpha && score < beta {
+                    score = -self.search_node(pos, new_depth, -beta, -alpha, ply + 1, true);
+                }
+                score
+            };
+            pos.undo_move(m);
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(m);
+                    self.update_pv(ply, m);
+                    if score >= beta {
+                        if quiet {
+                            self.update_quiet_stats(pos, m, depth, ply);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt
+            .store(key, best_move, score_to_tt(best, ply), depth, bound);
+        best
+    }
+    /// Searches captures only, or all the evasions if in check near the horizon.
+    fn quiesce(
+        &mut self,
+        pos: &mut Position,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+        qply: usize,
+    ) -> i32 {
+        self.pv[ply].clear();
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluator.evaluate(pos);
+        }
+        let in_check = pos.in_check() && qply < QUIESCENCE_EVASION_PLY;
+        let mut best = -INFINITE;
+        if !in_check {
+            best = self.evaluator.evaluate(pos);
+            if best >= beta {
+                return best;
+            }
+            alpha = alpha.max(best);
+        }
+        let mut moves = pos.legal_moves();
+        if moves.is_empty() && pos.in_check() {
+            return -MATE + ply as i32;
+        }
+        if !in_check {
+            moves.retain(|m| pos.piece_at(m.to()).is_some());
+        }
+        self.order(pos, &mut moves, ply, None);
+        for m in moves {
+            pos.do_move(m);
+            let score = -self.quiesce(pos, -beta, -alpha, ply + 1, qply + 1);
+            pos.undo_move(m);
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, m);
+                    if score >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        best
+    }
+    /// Orders the moves: the move from the transposition table, the move of the last principal
+    /// variation, captures by MVV-LVA, killer moves, and the other moves by the history.
+    fn order(&self, pos: &Position, moves: &mut [Move], ply: usize, tt_move: Option<Move>) {
+        let pv_move = self.prev_pv.get(ply).copied();
+        moves.sort_by_cached_key(|&m| {
+            if Some(m) == tt_move {
+                return i32::MIN;
+            }
+            if Some(m) == pv_move {
+                return i32::MIN + 1;
+            }
+            if let Some(captured) = pos.piece_at(m.to()) {
+                let victim = MaterialEvaluator::PIECE_VALUES[captured.piece_kind().array_index()];
+                let attacker = match m {
+                    Move::Normal { from, .. } => pos.piece_at(from).map_or(0, |p| {
+                        MaterialEvaluator::PIECE_VALUES[p.piece_kind().array_index()]
+                    }),
+                    Move::Drop { .. } => 0,
+                };
+                return -(1 << 24) - victim * 16 + attacker / 16;
+            }
+            if let Some(i) = self.killers[ply].iter().position(|&k| k == Some(m)) {
+                return -(1 << 20) + i as i32;
+            }
+            -self.history[history_index(pos, m)][m.to().array_index()]
+        });
+    }
+    fn update_pv(&mut self, ply: usize, m: Move) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(m);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+    fn update_quiet_stats(&mut self, pos: &Position, m: Move, depth: i32, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+        let history = &mut self.history[history_index(pos, m)][m.to().array_index()];
+        *history = (*history + depth * depth).min(1 << 16);
+    }
+}
+
+/// Mate scores are stored relative to the node, instead of the root.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        s if s >= MATE_IN_MAX_PLY => s + ply as i32,
+        s if s <= -MATE_IN_MAX_PLY => s - ply as i32,
+        s => s,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        s if s >= MATE_IN_MAX_PLY => s - ply as i32,
+        s if s <= -MATE_IN_MAX_PLY => s + ply as i32,
+        s => s,
+    }
+}
+
+fn history_index(pos: &Position, m: Move) -> usize {
+    let p = match m {
+        Move::Normal { from, .. } => pos.piece_at(from).expect("no piece to move"),
+        Move::Drop { piece, .. } => piece,
+    };
+    p.color().array_index() * PieceKind::NUM + p.piece_kind().array_index()
+}
+
+/// Returns whether the move is a capture or a promotion, which is not reduced in the search.
+fn is_tactical(pos: &Position, m: Move) -> bool {
+    pos.piece_at(m.to()).is_some() || matches!(m, Move::Normal { promote: true, .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::Piece;
+    use shogi_usi_parser::FromUsi;
+
+    fn depth_limits(depth: u32) -> Limits {
+        Limits {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mate() {
+        let pos = position(MATE_IN_ONE);
+        let result = Searcher::new(MaterialEvaluator).search(&pos, &depth_limits(5));
+        let m = Move::Drop {
+            to: Square::SQ_2B,
+            piece: Piece::B_G,
+        };
+        assert_eq!(Some(m), result.best_move);
+        assert_eq!(vec![m], result.pv);
+        assert_eq!(Some(1), result.mate_ply());
+        assert_eq!(1, result.depth);
+
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  *  *  *  *  *  *  *
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  * +OU *  *  *  *
+        // P+00KI00KI
+        // +
+        let pos = position("sfen 7nk/9/9/9/9/9/9/9/4K4 b 2G 1");
+        let result = Searcher::new(MaterialEvaluator).search(&pos, &depth_limits(7));
+        assert_eq!(Some(3), result.mate_ply());
+        assert_eq!(3, result.pv.len());
+
+        // no legal moves
+        let pos = position("sfen 8k/7G1/7P1/9/9/9/9/9/5K3 w 2r2b3g4s4n4l17p 1");
+        let result = Searcher::new(MaterialEvaluator).search(&pos, &depth_limits(3));
+        assert_eq!(None, result.best_move);
+        assert_eq!(Some(0), result.mate_ply());
+    }
+
+    #[test]
+    fn perpetual_check() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // ...
+        // P9+OU *  *  *  *  *  * +HI *
+        // P-00AL
+        // +
+        let mut pos = position("sfen 8k/9/9/9/9/9/9/9/K6R1 b 2b4g4s4n4l18p 1");
+        for m in ["2i1i", "1a2a", "1i2i", "2a1a", "2i1i"] {
+            pos.do_move(Move::from_usi(m).expect("failed to parse"));
+        }
+        // white repeats the position while black gives continuous checks
+        let result = Searcher::new(MaterialEvaluator).search(&pos, &depth_limits(3));
+        assert_eq!(Move::from_usi("1a2a").ok(), result.best_move);
+        assert_eq!(MATE_IN_MAX_PLY - 1, result.score);
+        assert_eq!(None, result.mate_ply());
+    }
+
+    #[test]
+    fn captures() {
+        // the rook can be captured for free
+        let pos = position("sfen 4k4/9/9/9/9/9/5r3/9/4K2B1 b - 1");
+        let result = Searcher::new(MaterialEvaluator).search(&pos, &depth_limits(4));
+        assert_eq!(
+            Some(Move::Normal {
+                from: Square::SQ_2I,
+                to: Square::SQ_4G,
+                promote: false,
+            }),
+            result.best_move
+        );
+        assert!(result.score > 0);
+    }
+
+    #[test]
+    fn pluggable_evaluation() {
+        // prefers to keep the king in the center files
+        struct Center;
+        impl Evaluate for Center {
+            fn evaluate(&mut self, pos: &Position) -> i32 {
+                let file = |c: Color| {
+                    Square::all()
+                        .find(|&sq| pos.piece_at(sq) == Some(Piece::new(PieceKind::King, c)))
+                        .map_or(0, |sq| 5 - i32::from(sq.file()).abs_diff(5) as i32)
+                };
+                let c = pos.side_to_move();
+                file(c) - file(c.flip())
+            }
+        }
+        let pos = position("sfen 4k4/9/9/9/9/9/9/9/K8 b - 1");
+        let mut searcher = Searcher::new(Center);
+        let result = searcher.search(&pos, &depth_limits(3));
+        assert_eq!(Some(8), result.best_move.map(|m| m.to().file()));
+        assert_eq!(3, result.pv.len());
+    }
+
+    #[test]
+    fn table() {
+        let pos = Position::default();
+        let tt = Arc::new(TranspositionTable::new(1));
+        let mut searcher = Searcher::with_table(MaterialEvaluator, Arc::clone(&tt));
+        let first = searcher.search(&pos, &depth_limits(4));
+        assert!(tt.probe(pos.key()).is_some());
+        assert!(tt.hashfull() > 0);
+        // the second search benefits from the stored results
+        let second = searcher.search(&pos, &depth_limits(4));
+        assert!(second.nodes < first.nodes);
+        searcher.clear();
+        assert_eq!(None, tt.probe(pos.key()));
+    }
+
+    #[test]
+    fn limits() {
+        let pos = Position::default();
+        let mut searcher = Searcher::new(MaterialEvaluator);
+        let mut depths = Vec::new();
+        let result = searcher.search_with(
+            &pos,
+            &Limits {
+                depth: Some(4),
+                ..Default::default()
+            },
+            |result| depths.push(result.depth),
+        );
+        assert_eq!(vec![1, 2, 3, 4], depths);
+        assert!(pos
+            .legal_moves()
+            .contains(&result.best_move.expect("no best move")));
+        assert_eq!(result.best_move, result.pv.first().copied());
+
+        let nodes = 1000;
+        let result = searcher.search(
+            &pos,
+            &Limits {
+                nodes: Some(nodes),
+                ..Default::default()
+            },
+        );
+        assert!(result.nodes <= nodes);
+        assert!(result.best_move.is_some());
+
+        let stop = Arc::new(AtomicBool::new(true));
+        let result = searcher.search(
+            &pos,
+            &Limits {
+                stop,
+                ..Default::default()
+            },
+        );
+        assert_eq!(0, result.depth);
+        assert!(result.best_move.is_some());
+    }
+}
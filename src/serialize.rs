@@ -0,0 +1,91 @@
+//! Serialization of [`Position`] as the initial position in SFEN and the moves in USI,
+//! from which the history is rebuilt.
+use crate::Position;
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use shogi_core::{Move, PartialPosition, ToUsi};
+use shogi_usi_parser::FromUsi;
+
+#[derive(Serialize, Deserialize)]
+struct Game {
+    /// Initial position, without the `sfen` prefix
+    sfen: String,
+    moves: Vec<String>,
+}
+
+/// Fails if a null move has been made, which can't be written in USI.
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.has_null_move() {
+            return Err(ser::Error::custom("null move in the history"));
+        }
+        Game {
+            sfen: self.initial_position().to_sfen_owned(),
+            moves: self.moves().iter().map(Move::to_usi_owned).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Replays the moves, which fails if any of them is not legal.
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let game = Game::deserialize(deserializer)?;
+        let partial = PartialPosition::from_usi(&format!("sfen {}", game.sfen))
+            .map_err(|_| de::Error::custom(format!("invalid sfen: {}", game.sfen)))?;
+        let mut pos = Position::new(partial);
+        for s in game.moves {
+            match Move::from_usi(&s) {
+                Ok(m) if pos.legal_moves().contains(&m) => pos.do_move(m),
+                _ => return Err(de::Error::custom(format!("illegal move: {s}"))),
+            }
+        }
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::Square;
+
+    #[test]
+    fn roundtrip() {
+        let mut pos = Position::default();
+        let json = serde_json::to_string(&pos).expect("failed to serialize");
+        assert_eq!(
+            r#"{"sfen":"lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1","moves":[]}"#,
+            json
+        );
+        for m in ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"] {
+            pos.do_move(Move::from_usi(m).expect("failed to parse"));
+        }
+        let json = serde_json::to_string(&pos).expect("failed to serialize");
+        assert_eq!(
+            r#"{"sfen":"lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1","moves":["7g7f","3c3d","8h2b+","3a2b","B*4e"]}"#,
+            json
+        );
+        let de = serde_json::from_str::<Position>(&json).expect("failed to deserialize");
+        assert_eq!(pos.moves(), de.moves());
+        assert_eq!(pos.key(), de.key());
+        assert_eq!(pos.ply(), de.ply());
+        assert_eq!(pos.piece_at(Square::SQ_2B), de.piece_at(Square::SQ_2B));
+        assert_eq!(pos.legal_moves(), de.legal_moves());
+    }
+
+    #[test]
+    fn errors() {
+        let mut pos = Position::default();
+        pos.do_move(Move::from_usi("7g7f").expect("failed to parse"));
+        pos.do_null_move();
+        assert!(serde_json::to_string(&pos).is_err());
+
+        for json in [
+            r#"{"sfen":"lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1","moves":["7g7e"]}"#,
+            r#"{"sfen":"lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1","moves":["7g"]}"#,
+            r#"{"sfen":"lnsgkgsnl/1r5b1/ppppppppp b - 1","moves":[]}"#,
+            r#"{"moves":[]}"#,
+        ] {
+            assert!(serde_json::from_str::<Position>(json).is_err(), "{json}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Lazy SMP: parallel search by independent searchers sharing a transposition table.
+use crate::search::{Evaluate, Limits, SearchResult, Searcher};
+use crate::tt::TranspositionTable;
+use crate::Position;
+use shogi_core::Move;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Runs a [`Searcher`] per thread over the same position.
+///
+/// The searchers share the transposition table, and the helpers start from different depths
+/// so that they don't follow the same path. The first searcher runs on the calling thread and
+/// decides when to stop, then the best move is chosen by the votes of all the threads.
+/// With a single thread, no thread is spawned and the search is deterministic.
+pub struct ThreadPool<E> {
+    searchers: Vec<Searcher<E>>,
+    evaluator: E,
+    tt: Arc<TranspositionTable>,
+}
+
+impl<E> ThreadPool<E>
+where
+    E: Evaluate + Clone + Send,
+{
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(evaluator: E, threads: usize, tt: Arc<TranspositionTable>) -> Self {
+        assert!(threads > 0, "no threads");
+        let mut pool = Self {
+            searchers: Vec::new(),
+            evaluator,
+            tt,
+        };
+        pool.set_threads(threads);
+        pool
+    }
+    pub fn threads(&self) -> usize {
+        self.searchers.len()
+    }
+    /// Changes the number of threads. The move ordering heuristics of the removed ones are lost.
+    pub fn set_threads(&mut self, threads: usize) {
+        let threads = threads.max(1);
+        self.searchers.truncate(threads);
+        while self.searchers.len() < threads {
+            let searcher = Searcher::with_table(self.evaluator.clone(), Arc::clone(&self.tt));
+            self.searchers.push(searcher);
+        }
+    }
+    pub fn table(&self) -> &Arc<TranspositionTable> {
+        &self.tt
+    }
+    /// Forgets the transposition table and the move ordering heuristics, for a new game.
+    pub fn clear(&mut self) {
+        self.searchers.iter_mut().for_each(Searcher::clear);
+    }
+    pub fn search(&mut self, pos: &Position, limits: &Limits) -> SearchResult {
+        self.search_with(pos, limits, |_| {})
+    }
+    /// Searches the position with all the threads, calling `on_iteration` with the result
+    /// of each completed iteration of the first thread. The returned result has the total nodes.
+    ///
+    /// The node limit applies to the first thread only.
+    pub fn search_with<F>(
+        &mut self,
+        pos: &Position,
+        limits: &Limits,
+        on_iteration: F,
+    ) -> SearchResult
+    where
+        F: FnMut(&SearchResult),
+    {
+        self.tt.new_search();
+        let stop = Arc::new(AtomicBool::new(false));
+        let helper_limits = Limits {
+            depth: limits.depth,
+            nodes: None,
+            time: limits.time,
+            stop: Arc::clone(&stop),
+        };
+        let (main, helpers) = self.searchers.split_first_mut().expect("no searchers");
+        let mut results = thread::scope(|s| {
+            let handles = helpers
+                .iter_mut()
+                .enumerate()
+                .map(|(i, searcher)| {
+                    let limits = helper_limits.clone();
+                    s.spawn(move || searcher.run(pos, &limits, depth_offset(i + 1), |_| {}))
+                })
+                .collect::<Vec<_>>();
+            let result = main.run(pos, limits, 0, on_iteration);
+            stop.store(true, Ordering::Relaxed);
+            let mut results = vec![result];
+            results.extend(
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("search thread panicked")),
+            );
+            results
+        });
+        let nodes = results.iter().map(|result| result.nodes).sum();
+        let mut best = results.swap_remove(select(&results));
+        best.nodes = nodes;
+        best
+    }
+}
+
+/// Returns the depth to start from for the thread, skipping the first iteration on every other thread.
+fn depth_offset(thread: usize) -> u32 {
+    (thread % 2) as u32
+}
+
+/// Returns the index of the best result. A mate is taken as it is, otherwise the threads vote
+/// for their best moves weighted by the score and the depth.
+fn select(results: &[SearchResult]) -> usize {
+    let best_score = |i: &usize| (results[*i].depth > 0, results[*i].score);
+    let best = (0..results.len())
+        .max_by_key(best_score)
+        .unwrap_or_default();
+    if results[best].mate_ply().is_some_and(|ply| ply > 0) {
+        return best;
+    }
+    let min_score = results
+        .iter()
+        .map(|result| result.score)
+        .min()
+        .unwrap_or_default();
+    let mut votes = Vec::<(Move, i64)>::new();
+    for result in results {
+        let Some(m) = result.best_move else {
+            continue;
+        };
+        let vote = i64::from(result.score - min_score + 14) * i64::from(result.depth);
+        match votes.iter_mut().find(|(vm, _)| *vm == m) {
+            Some((_, v)) => *v += vote,
+            None => votes.push((m, vote)),
+        }
+    }
+    let vote_of = |result: &SearchResult| {
+        votes
+            .iter()
+            .find(|(m, _)| Some(*m) == result.best_move)
+            .map_or(0, |(_, v)| *v)
+    };
+    // the first thread wins ties
+    (0..results.len())
+        .rev()
+        .max_by_key(|&i| (vote_of(&results[i]), results[i].depth, results[i].score))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::MaterialEvaluator;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::{Piece, Square};
+
+    fn depth_limits(depth: u32) -> Limits {
+        Limits {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn single_thread() {
+        // the same as a single searcher, and deterministic
+        let pos = Position::default();
+        let mut pool = ThreadPool::new(MaterialEvaluator, 1, Arc::new(TranspositionTable::new(1)));
+        let mut searcher =
+            Searcher::with_table(MaterialEvaluator, Arc::new(TranspositionTable::new(1)));
+        let mut depths = Vec::new();
+        let result = pool.search_with(&pos, &depth_limits(4), |result| depths.push(result.depth));
+        assert_eq!(vec![1, 2, 3, 4], depths);
+        assert_eq!(searcher.search(&pos, &depth_limits(4)), result);
+    }
+
+    #[test]
+    fn multi_threads() {
+        let pos = position(MATE_IN_ONE);
+        let mut pool = ThreadPool::new(MaterialEvaluator, 4, Arc::new(TranspositionTable::new(1)));
+        assert_eq!(4, pool.threads());
+        let result = pool.search(&pos, &depth_limits(5));
+        assert_eq!(
+            Some(Move::Drop {
+                to: Square::SQ_2B,
+                piece: Piece::B_G,
+            }),
+            result.best_move
+        );
+        assert_eq!(Some(1), result.mate_ply());
+
+        // stopped by the first thread
+        pool.set_threads(2);
+        assert_eq!(2, pool.threads());
+        let pos = Position::default();
+        let result = pool.search(
+            &pos,
+            &Limits {
+                nodes: Some(10000),
+                ..Default::default()
+            },
+        );
+        assert!(pos
+            .legal_moves()
+            .contains(&result.best_move.expect("no best move")));
+        assert!(result.nodes >= 10000);
+    }
+
+    #[test]
+    fn voting() {
+        let pos = Position::default();
+        let moves = pos.legal_moves();
+        let result = |m: Move, score: i32, depth: u32| SearchResult {
+            best_move: Some(m),
+            score,
+            pv: vec![m],
+            depth,
+            nodes: 0,
+        };
+        // the majority wins over the single best score
+        let results = [
+            result(moves[0], 100, 10),
+            result(moves[1], 110, 10),
+            result(moves[0], 100, 10),
+        ];
+        assert_eq!(0, select(&results));
+        // the deeper one is preferred
+        let results = [result(moves[0], 100, 10), result(moves[1], 100, 12)];
+        assert_eq!(1, select(&results));
+        // a mate is taken
+        let results = [
+            result(moves[0], 100, 10),
+            result(moves[0], 100, 10),
+            result(moves[1], crate::search::MATE - 5, 6),
+        ];
+        assert_eq!(2, select(&results));
+        // an unfinished thread has no vote
+        let results = [result(moves[0], 0, 0), result(moves[1], -50, 3)];
+        assert_eq!(1, select(&results));
+    }
+}
@@ -0,0 +1,37 @@
+//! Positions and helpers shared by the tests.
+use crate::Position;
+use shogi_core::PartialPosition;
+use shogi_usi_parser::FromUsi;
+
+/// Mate in one by dropping the gold on 2b.
+///
+/// ```text
+/// P1 *  *  *  *  *  *  * -KE-OU
+/// P2 *  *  *  *  *  *  *  * -KY
+/// P3 *  *  *  *  *  *  * +FU-FU
+/// P4 *  *  *  *  *  *  *  *  *
+/// P5 *  *  *  *  *  *  *  *  *
+/// P6 *  *  *  *  *  *  *  *  *
+/// P7 *  *  *  *  *  *  *  *  *
+/// P8 *  *  *  *  *  *  *  *  *
+/// P9 *  *  *  *  * +OU *  *  *
+/// P+00KI
+/// P-00AL
+/// +
+/// ```
+pub(crate) const MATE_IN_ONE: &str = "sfen 7nk/8l/7Pp/9/9/9/9/9/5K3 b G2r2b3g4s3n3l16p 1";
+
+/// Positions covering the edge cases of the move generation.
+pub(crate) const MOVEGEN_POSITIONS: [&str; 4] = [
+    "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+    // maximum moves
+    "sfen R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+    // pawn drop mate
+    "sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1",
+    // checked by a dragon diagonally
+    "sfen 8k/9/6+R2/9/9/9/9/9/4K4 w - 1",
+];
+
+pub(crate) fn position(sfen: &str) -> Position {
+    Position::new(PartialPosition::from_usi(sfen).expect("failed to parse"))
+}
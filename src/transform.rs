@@ -0,0 +1,188 @@
+//! Symmetric transforms of positions and moves, for augmenting training data.
+//!
+//! - mirroring reflects the board left to right, keeping the colors
+//! - flipping rotates the board by 180 degrees and swaps the colors of the pieces, the hands and
+//!   the side to move, so the position is the same from the viewpoint of the side to move
+use crate::Position;
+use shogi_core::{Color, Move, Piece, Square};
+
+/// Returns the square reflected left to right, e.g. 7g to 3g.
+pub fn mirror_square(sq: Square) -> Square {
+    Square::new(10 - sq.file(), sq.rank()).expect("invalid square")
+}
+
+/// Returns the move in the position transformed by [`Position::mirrored`].
+pub fn mirror_move(m: Move) -> Move {
+    match m {
+        Move::Normal { from, to, promote } => Move::Normal {
+            from: mirror_square(from),
+            to: mirror_square(to),
+            promote,
+        },
+        Move::Drop { to, piece } => Move::Drop {
+            to: mirror_square(to),
+            piece,
+        },
+    }
+}
+
+/// Returns the move in the position transformed by [`Position::flipped`].
+pub fn flip_move(m: Move) -> Move {
+    match m {
+        Move::Normal { from, to, promote } => Move::Normal {
+            from: from.flip(),
+            to: to.flip(),
+            promote,
+        },
+        Move::Drop { to, piece } => Move::Drop {
+            to: to.flip(),
+            piece: flip_piece(piece),
+        },
+    }
+}
+
+fn flip_piece(p: Piece) -> Piece {
+    Piece::new(p.piece_kind(), p.color().flip())
+}
+
+impl Position {
+    /// Returns the position reflected left to right, without history.
+    pub fn mirrored(&self) -> Position {
+        let mut partial = self.to_partial_position();
+        for sq in Square::all() {
+            partial.piece_set(mirror_square(sq), self.piece_at(sq));
+        }
+        Position::new(partial)
+    }
+    /// Returns the position rotated by 180 degrees with the colors swapped, without history.
+    pub fn flipped(&self) -> Position {
+        let mut partial = self.to_partial_position();
+        for sq in Square::all() {
+            partial.piece_set(sq.flip(), self.piece_at(sq).map(flip_piece));
+        }
+        for c in Color::all() {
+            *partial.hand_of_a_player_mut(c.flip()) = self.hand(c);
+        }
+        partial.side_to_move_set(self.side_to_move().flip());
+        Position::new(partial)
+    }
+    /// Returns the smallest [`Position::key`] of the position, mirrored, flipped, and both,
+    /// which is the same for all of them to identify the symmetric positions.
+    pub fn canonical_key(&self) -> u64 {
+        let mirrored = self.mirrored();
+        let flipped = self.flipped();
+        [
+            self.key(),
+            mirrored.key(),
+            flipped.key(),
+            flipped.mirrored().key(),
+        ]
+        .into_iter()
+        .min()
+        .expect("empty keys")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE, MOVEGEN_POSITIONS};
+    use shogi_core::{PartialPosition, PieceKind, ToUsi};
+    use shogi_usi_parser::FromUsi;
+
+    #[test]
+    fn squares_and_moves() {
+        assert_eq!(Square::SQ_3G, mirror_square(Square::SQ_7G));
+        assert_eq!(Square::SQ_5A, mirror_square(Square::SQ_5A));
+        let m = Move::Normal {
+            from: Square::SQ_8H,
+            to: Square::SQ_2B,
+            promote: true,
+        };
+        assert_eq!(
+            Move::Normal {
+                from: Square::SQ_2H,
+                to: Square::SQ_8B,
+                promote: true,
+            },
+            mirror_move(m)
+        );
+        assert_eq!(
+            Move::Normal {
+                from: Square::SQ_2B,
+                to: Square::SQ_8H,
+                promote: true,
+            },
+            flip_move(m)
+        );
+        let m = Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::B_P,
+        };
+        assert_eq!(
+            Move::Drop {
+                to: Square::SQ_5E,
+                piece: Piece::W_P,
+            },
+            flip_move(m)
+        );
+        assert_eq!(m, mirror_move(m));
+    }
+
+    #[test]
+    fn positions() {
+        let mut pos = Position::default();
+        // the bishop and the rook are swapped by mirroring, but not by flipping
+        assert_eq!(Some(Piece::B_B), pos.mirrored().piece_at(Square::SQ_2H));
+        assert_eq!(Some(Piece::B_R), pos.mirrored().piece_at(Square::SQ_8H));
+        let mut partial = PartialPosition::startpos();
+        partial.side_to_move_set(Color::White);
+        assert_eq!(Position::new(partial), pos.flipped());
+
+        for m in ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"] {
+            pos.do_move(Move::from_usi(m).expect("failed to parse"));
+        }
+        let mirrored = pos.mirrored();
+        let flipped = pos.flipped();
+        assert_eq!(Some(Piece::B_B), mirrored.piece_at(Square::SQ_6E));
+        assert_eq!(Some(Piece::W_B), flipped.piece_at(Square::SQ_6E));
+        assert_eq!(Some(0), flipped.hand(Color::White).count(PieceKind::Bishop));
+        assert_eq!(Some(1), flipped.hand(Color::Black).count(PieceKind::Bishop));
+        assert_eq!(pos.side_to_move().flip(), flipped.side_to_move());
+        assert!(mirrored.moves().is_empty());
+        assert_eq!(Ok(()), mirrored.validate());
+        assert_eq!(Ok(()), flipped.validate());
+        // involutions
+        assert_eq!(pos, mirrored.mirrored());
+        assert_eq!(pos, flipped.flipped());
+        assert_ne!(pos, mirrored);
+    }
+
+    #[test]
+    fn legal_moves() {
+        for sfen in MOVEGEN_POSITIONS {
+            let pos = position(sfen);
+            let expected = pos.legal_moves();
+            for (transformed, transform) in [
+                (pos.mirrored(), mirror_move as fn(Move) -> Move),
+                (pos.flipped(), flip_move),
+            ] {
+                let mut actual = transformed.legal_moves().to_vec();
+                let mut moves = expected.iter().map(|&m| transform(m)).collect::<Vec<_>>();
+                actual.sort_by_key(|m| m.to_usi_owned());
+                moves.sort_by_key(|m| m.to_usi_owned());
+                assert_eq!(moves, actual, "{sfen}");
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_key() {
+        let pos = position(MATE_IN_ONE);
+        let key = pos.canonical_key();
+        assert_eq!(key, pos.mirrored().canonical_key());
+        assert_eq!(key, pos.flipped().canonical_key());
+        assert_eq!(key, pos.flipped().mirrored().canonical_key());
+        assert_ne!(key, Position::default().canonical_key());
+    }
+}
@@ -0,0 +1,398 @@
+//! Df-pn solver of tsume shogi (mate problems).
+//!
+//! The attacker, the side to move at the root, must give check on every move. The proof and
+//! disproof numbers are stored per board with both hands, so that a proof with fewer pieces in
+//! the attacker's hand (and more in the defender's) is reused for more, and a disproof for fewer.
+//! Pawn-drop mates are excluded by [`Position::legal_moves`], and any repetition is a failure of
+//! the attacker. A disproof by a repetition depends on the path to the position, so a mate may be
+//! missed through a transposition, but a found mate is always sound.
+use crate::Position;
+use shogi_core::{Color, Hand, Move};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const INFINITE: u32 = u32::MAX;
+/// Finite numbers are capped below [`INFINITE`], which means proved or disproved.
+const MAX_NUMBER: u32 = INFINITE - 1;
+/// Maximum length of a branch, beyond which it's treated as a disproof along the path.
+const MAX_PLY: usize = 256;
+
+/// Result of [`Solver::solve`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    /// Moves to the checkmate, with the longest defense found.
+    Mate(Vec<Move>),
+    NoMate,
+    /// The node limit is reached, or the search is stopped.
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    attacker: Hand,
+    defender: Hand,
+    pn: u32,
+    dn: u32,
+    /// Number of plies to the checkmate, for a proved node
+    length: u32,
+}
+
+/// Df-pn solver with the table of proof and disproof numbers.
+pub struct Solver {
+    table: HashMap<u64, Vec<Entry>>,
+    nodes: u64,
+    max_nodes: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl Solver {
+    /// Creates a solver which gives up after visiting `max_nodes` nodes.
+    pub fn new(max_nodes: u64) -> Self {
+        Self {
+            table: HashMap::new(),
+            nodes: 0,
+            max_nodes,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    /// Sets the flag to stop the search from another thread.
+    pub fn set_stop(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+    /// Returns the number of the nodes visited so far.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+    /// Searches for a checkmate by the side to move. The table is kept between calls.
+    pub fn solve(&mut self, pos: &Position) -> Solution {
+        let mut pos = pos.clone();
+        let attacker = pos.side_to_move();
+        let (mut pn, mut dn, _) = self.lookup(&pos, attacker);
+        if pn != 0 && dn != 0 {
+            self.mid(&mut pos, true, INFINITE, INFINITE, 0);
+            (pn, dn, _) = self.lookup(&pos, attacker);
+        }
+        if pn == 0 {
+            Solution::Mate(self.mating_sequence(&mut pos))
+        } else if dn == 0 {
+            Solution::NoMate
+        } else {
+            Solution::Unknown
+        }
+    }
+    /// Expands the node until its proof or disproof number reaches the threshold.
+    fn mid(&mut self, pos: &mut Position, or_node: bool, thpn: u32, thdn: u32, ply: usize) {
+        self.nodes += 1;
+        let attacker = if or_node {
+            pos.side_to_move()
+        } else {
+            pos.side_to_move().flip()
+        };
+        let moves = moves(pos, or_node);
+        if moves.is_empty() {
+            // no checks, or checkmated
+            let (pn, dn) = if or_node {
+                (INFINITE, 0)
+            } else {
+                (0, INFINITE)
+            };
+            self.store(pos, attacker, pn, dn, 0);
+            return;
+        }
+        loop {
+            let children = moves
+                .iter()
+                .map(|&m| self.child_numbers(pos, m, attacker, ply))
+                .collect::<Vec<_>>();
+            // phi and delta are the numbers from the point of view of the player to move
+            let mut best = 0;
+            let (mut phi_min, mut phi_second) = (INFINITE, INFINITE);
+            let mut delta_sum = 0u32;
+            for (i, &(pn, dn, _)) in children.iter().enumerate() {
+                let (phi_c, delta_c) = if or_node { (pn, dn) } else { (dn, pn) };
+                if phi_c < phi_min {
+                    phi_second = phi_min;
+                    phi_min = phi_c;
+                    best = i;
+                } else if phi_c < phi_second {
+                    phi_second = phi_c;
+                }
+                delta_sum = add(delta_sum, delta_c);
+            }
+            let (pn, dn) = if or_node {
+                (phi_min, delta_sum)
+            } else {
+                (delta_sum, phi_min)
+            };
+            let length = if pn == 0 {
+                let lengths = children.iter().filter(|c| c.0 == 0).map(|c| c.2);
+                1 + if or_node {
+                    lengths.min()
+                } else {
+                    lengths.max()
+                }
+                .unwrap_or_default()
+            } else {
+                0
+            };
+            let (th_phi, th_delta) = if or_node { (thpn, thdn) } else { (thdn, thpn) };
+            if pn >= thpn
+                || dn >= thdn
+                || pn == 0
+                || dn == 0
+                || self.nodes >= self.max_nodes
+                || self.stop.load(Ordering::Relaxed)
+            {
+                self.store(pos, attacker, pn, dn, length);
+                return;
+            }
+            let (pn_c, dn_c, _) = children[best];
+            let delta_c = if or_node { dn_c } else { pn_c };
+            let child_phi = th_phi.min(add(phi_second, 1));
+            let child_delta = add(th_delta.saturating_sub(delta_sum), delta_c);
+            let (child_pn, child_dn) = if or_node {
+                (child_phi, child_delta)
+            } else {
+                (child_delta, child_phi)
+            };
+            let m = moves[best];
+            pos.do_move(m);
+            self.mid(pos, !or_node, child_pn, child_dn, ply + 1);
+            pos.undo_move(m);
+        }
+    }
+    /// Returns the numbers of the position after the move.
+    fn child_numbers(
+        &self,
+        pos: &mut Position,
+        m: Move,
+        attacker: Color,
+        ply: usize,
+    ) -> (u32, u32, u32) {
+        pos.do_move(m);
+        let numbers = if pos.repetition().is_some() || ply + 1 >= MAX_PLY {
+            (INFINITE, 0, 0)
+        } else {
+            self.lookup(pos, attacker)
+        };
+        pos.undo_move(m);
+        numbers
+    }
+    /// Returns `(pn, dn, length)` of the position, from the proofs and disproofs of the dominated
+    /// hands if any.
+    fn lookup(&self, pos: &Position, attacker: Color) -> (u32, u32, u32) {
+        let (a, d) = (pos.hand(attacker), pos.hand(attacker.flip()));
+        let mut numbers = (1, 1, 0);
+        for entry in self.table.get(&pos.keys().0).into_iter().flatten() {
+            if entry.pn == 0 && dominates(a, entry.attacker) && dominates(entry.defender, d) {
+                return (0, INFINITE, entry.length);
+            }
+            if entry.dn == 0 && dominates(entry.attacker, a) && dominates(d, entry.defender) {
+                return (INFINITE, 0, 0);
+            }
+            if entry.attacker == a && entry.defender == d {
+                numbers = (entry.pn, entry.dn, entry.length);
+            }
+        }
+        numbers
+    }
+    fn store(&mut self, pos: &Position, attacker: Color, pn: u32, dn: u32, length: u32) {
+        let (a, d) = (pos.hand(attacker), pos.hand(attacker.flip()));
+        let entries = self.table.entry(pos.keys().0).or_default();
+        let entry = Entry {
+            attacker: a,
+            defender: d,
+            pn,
+            dn,
+            length,
+        };
+        match entries
+            .iter_mut()
+            .find(|e| e.attacker == a && e.defender == d)
+        {
+            Some(e) => *e = entry,
+            None => entries.push(entry),
+        }
+    }
+    /// Follows the proved moves: the shortest one for the attacker, the longest one for the defender.
+    fn mating_sequence(&self, pos: &mut Position) -> Vec<Move> {
+        let attacker = pos.side_to_move();
+        let mut sequence = Vec::new();
+        let mut or_node = true;
+        while sequence.len() < MAX_PLY {
+            let proved = moves(pos, or_node)
+                .into_iter()
+                .filter_map(|m| {
+                    let (pn, _, length) = self.child_numbers(pos, m, attacker, sequence.len());
+                    (pn == 0).then_some((m, length))
+                })
+                .collect::<Vec<_>>();
+            let next = if or_node {
+                proved.into_iter().min_by_key(|&(_, length)| length)
+            } else {
+                proved.into_iter().max_by_key(|&(_, length)| length)
+            };
+            let Some((m, _)) = next else {
+                break;
+            };
+            pos.do_move(m);
+            sequence.push(m);
+            or_node = !or_node;
+        }
+        sequence
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new(u64::MAX)
+    }
+}
+
+/// Returns the checks for the attacker, or all the legal moves for the defender.
+fn moves(pos: &Position, or_node: bool) -> Vec<Move> {
+    let moves = pos.legal_moves();
+    if or_node {
+        moves
+            .into_iter()
+            .filter(|&m| pos.is_check_move(m))
+            .collect()
+    } else {
+        moves.to_vec()
+    }
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    if a == INFINITE || b == INFINITE {
+        INFINITE
+    } else {
+        a.saturating_add(b).min(MAX_NUMBER)
+    }
+}
+
+/// Returns whether `a` has at least as many pieces of each kind as `b`.
+fn dominates(a: Hand, b: Hand) -> bool {
+    Hand::all_hand_pieces().all(|pk| a.count(pk) >= b.count(pk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{position, MATE_IN_ONE};
+    use shogi_core::{Piece, Square};
+
+    fn mate(solution: Solution) -> Vec<Move> {
+        match solution {
+            Solution::Mate(moves) => moves,
+            solution => panic!("not mate: {solution:?}"),
+        }
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let pos = position(MATE_IN_ONE);
+        let mut solver = Solver::default();
+        assert_eq!(
+            vec![Move::Drop {
+                to: Square::SQ_2B,
+                piece: Piece::B_G,
+            }],
+            mate(solver.solve(&pos))
+        );
+        assert!(solver.nodes() > 0);
+    }
+
+    #[test]
+    fn longer_mates() {
+        // P1 *  *  *  *  *  *  *  * -OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  *  *  *  *  *  * +FU
+        // P4 *  *  *  *  *  *  *  *  *
+        // P5 *  *  *  *  *  *  *  *  *
+        // P6 *  *  *  *  *  *  *  *  *
+        // P7 *  *  *  *  *  *  *  *  *
+        // P8 *  *  *  *  *  *  *  *  *
+        // P9 *  *  *  * +OU *  *  *  *
+        // P+00HI00GI
+        // +
+        for (sfen, length) in [
+            ("sfen 8k/9/8P/9/9/9/9/9/4K4 b RS 1", 3),
+            ("sfen 8k/9/9/9/9/9/9/9/4K4 b B2G 1", 7),
+        ] {
+            let mut pos = position(sfen);
+            let moves = mate(Solver::default().solve(&pos));
+            assert_eq!(length, moves.len());
+            for (i, m) in moves.into_iter().enumerate() {
+                if i % 2 == 0 {
+                    assert!(pos.is_check_move(m));
+                }
+                pos.do_move(m);
+            }
+            assert!(pos.in_check());
+            assert!(pos.legal_moves().is_empty());
+        }
+    }
+
+    #[test]
+    fn uchifuzume() {
+        // P1 *  *  *  *  *  *  * -KE-OU
+        // P2 *  *  *  *  *  *  *  *  *
+        // P3 *  *  *  *  *  *  * +KI *
+        // ...
+        // P9 *  *  *  * +OU *  *  *  *
+        // P+00FU
+        // +
+        let pos = position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        assert_eq!(Solution::NoMate, Solver::default().solve(&pos));
+        // a lance drop is mate
+        let pos = position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b L 1");
+        assert_eq!(
+            vec![Move::Drop {
+                to: Square::SQ_1B,
+                piece: Piece::B_L,
+            }],
+            mate(Solver::default().solve(&pos))
+        );
+    }
+
+    #[test]
+    fn hand_dominance() {
+        let mut solver = Solver::default();
+        let pos = position(MATE_IN_ONE);
+        assert!(matches!(solver.solve(&pos), Solution::Mate(_)));
+        let nodes = solver.nodes();
+        // proved by the table with more pieces in hand
+        let pos = position("sfen 7nk/8l/7Pp/9/9/9/9/9/5K3 b GS2r2b3g3s3n3l16p 1");
+        assert!(matches!(solver.solve(&pos), Solution::Mate(_)));
+        assert_eq!(nodes, solver.nodes());
+
+        let pos = position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        assert_eq!(Solution::NoMate, solver.solve(&pos));
+        let nodes = solver.nodes();
+        // disproved by the table with fewer pieces in hand
+        let pos = position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b - 1");
+        assert_eq!(Solution::NoMate, solver.solve(&pos));
+        assert_eq!(nodes, solver.nodes());
+    }
+
+    #[test]
+    fn repetition() {
+        // perpetual checks by the rook
+        let pos = position("sfen 8k/9/9/9/9/9/9/9/K6R1 b 2b4g4s4n4l18p 1");
+        assert_eq!(Solution::NoMate, Solver::default().solve(&pos));
+    }
+
+    #[test]
+    fn node_limit() {
+        let pos = position("sfen 8k/9/9/9/9/9/9/9/4K4 b B2G 1");
+        let mut solver = Solver::new(1);
+        assert_eq!(Solution::Unknown, solver.solve(&pos));
+        assert_eq!(1, solver.nodes());
+
+        let mut solver = Solver::default();
+        solver.set_stop(Arc::new(AtomicBool::new(true)));
+        assert_eq!(Solution::Unknown, solver.solve(&pos));
+        assert_eq!(1, solver.nodes());
+    }
+}
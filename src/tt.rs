@@ -0,0 +1,275 @@
+//! Transposition table shared between search threads.
+//!
+//! Each entry is packed into a single `AtomicU64`, so that the table can be read and written
+//! concurrently without locks. Only 16 bits of the key are stored to tell the positions apart,
+//! so a probed move must be checked to be legal before it is played.
+use crate::hcp::{decode_move, encode_move};
+use shogi_core::{Color, Move};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+const BUCKET_SIZE: usize = 4;
+/// The generation is stored in 6 bits.
+const GENERATION_MASK: u8 = 0x3f;
+
+/// Kind of the stored score.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is an upper bound, the search failed low.
+    Upper = 1,
+    /// The score is a lower bound, the search failed high.
+    Lower = 2,
+    Exact = 3,
+}
+
+/// Search result stored in the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TtEntry {
+    /// Best move in the 16-bit format of [`encode_move`], or `0` for no move.
+    pub move16: u16,
+    pub score: i16,
+    pub depth: i8,
+    pub bound: Bound,
+    pub generation: u8,
+}
+
+impl TtEntry {
+    /// Returns the best move, which may not be legal in case of a key collision.
+    pub fn best_move(&self, c: Color) -> Option<Move> {
+        decode_move(self.move16, c)
+    }
+    fn pack(&self, key: u64) -> u64 {
+        (key & 0xffff)
+            | u64::from(self.move16) << 16
+            | u64::from(self.score as u16) << 32
+            | u64::from(self.depth as u8) << 48
+            | u64::from(self.bound as u8) << 56
+            | u64::from(self.generation & GENERATION_MASK) << 58
+    }
+    fn unpack(data: u64) -> Option<Self> {
+        let bound = match (data >> 56) & 0x3 {
+            1 => Bound::Upper,
+            2 => Bound::Lower,
+            3 => Bound::Exact,
+            _ => return None,
+        };
+        Some(Self {
+            move16: (data >> 16) as u16,
+            score: (data >> 32) as u16 as i16,
+            depth: (data >> 48) as u8 as i8,
+            bound,
+            generation: (data >> 58) as u8,
+        })
+    }
+}
+
+#[repr(align(32))]
+#[derive(Default)]
+struct Bucket([AtomicU64; BUCKET_SIZE]);
+
+/// Lock-free transposition table keyed by [`Position::key`](crate::Position::key).
+pub struct TranspositionTable {
+    buckets: Box<[Bucket]>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// Creates a table of the size in megabytes.
+    pub fn new(mb: usize) -> Self {
+        let len = (mb * 1024 * 1024 / std::mem::size_of::<Bucket>()).max(1);
+        Self {
+            buckets: (0..len).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+    /// Returns the number of entries.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+    pub fn clear(&self) {
+        for bucket in self.buckets.iter() {
+            for entry in &bucket.0 {
+                entry.store(0, Ordering::Relaxed);
+            }
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+    /// Starts a new search, so that the entries of the older searches are replaced first.
+    pub fn new_search(&self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation.store(
+            generation.wrapping_add(1) & GENERATION_MASK,
+            Ordering::Relaxed,
+        );
+    }
+    pub fn generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed)
+    }
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        self.bucket(key).0.iter().find_map(|entry| {
+            let data = entry.load(Ordering::Relaxed);
+            if data & 0xffff == key & 0xffff {
+                TtEntry::unpack(data)
+            } else {
+                None
+            }
+        })
+    }
+    /// Stores the result, replacing the entry of the same position,
+    /// or the one of the shallowest and oldest search in the bucket.
+    pub fn store(&self, key: u64, m: Option<Move>, score: i32, depth: i32, bound: Bound) {
+        let generation = self.generation();
+        let bucket = self.bucket(key);
+        let mut replace = &bucket.0[0];
+        let mut worst = i32::MAX;
+        let mut move16 = m.map_or(0, encode_move);
+        for entry in &bucket.0 {
+            let data = entry.load(Ordering::Relaxed);
+            let Some(old) = TtEntry::unpack(data) else {
+                replace = entry;
+                break;
+            };
+            if data & 0xffff == key & 0xffff {
+                // keep the best move of the same position
+                if move16 == 0 {
+                    move16 = old.move16;
+                }
+                replace = entry;
+                break;
+            }
+            let age = i32::from(generation.wrapping_sub(old.generation) & GENERATION_MASK);
+            let value = i32::from(old.depth) - 8 * age;
+            if value < worst {
+                worst = value;
+                replace = entry;
+            }
+        }
+        let entry = TtEntry {
+            move16,
+            score: score.clamp(i16::MIN.into(), i16::MAX.into()) as i16,
+            depth: depth.clamp(i8::MIN.into(), i8::MAX.into()) as i8,
+            bound,
+            generation,
+        };
+        replace.store(entry.pack(key), Ordering::Relaxed);
+    }
+    /// Hints the CPU to load the bucket of the key into the cache.
+    #[allow(unused_variables)]
+    pub fn prefetch(&self, key: u64) {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            _mm_prefetch::<_MM_HINT_T0>(self.bucket(key) as *const Bucket as *const i8);
+        }
+    }
+    /// Returns the usage by the current search in permill, estimated from the first 1000 entries.
+    pub fn hashfull(&self) -> u32 {
+        let generation = self.generation();
+        let buckets = &self.buckets[..self.buckets.len().min(1000 / BUCKET_SIZE)];
+        let used = buckets
+            .iter()
+            .flat_map(|bucket| &bucket.0)
+            .filter_map(|entry| TtEntry::unpack(entry.load(Ordering::Relaxed)))
+            .filter(|entry| entry.generation == generation)
+            .count();
+        (used * 1000 / (buckets.len() * BUCKET_SIZE)) as u32
+    }
+    fn bucket(&self, key: u64) -> &Bucket {
+        let index = (u128::from(key) * self.buckets.len() as u128) >> 64;
+        &self.buckets[index as usize]
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Position;
+    use shogi_core::{Piece, Square};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(1024 * 1024 / 8, tt.capacity());
+        let pos = Position::default();
+        let m = pos.legal_moves()[0];
+        assert_eq!(None, tt.probe(pos.key()));
+        tt.store(pos.key(), Some(m), -123, 5, Bound::Lower);
+        let entry = tt.probe(pos.key()).expect("not found");
+        assert_eq!(Some(m), entry.best_move(pos.side_to_move()));
+        assert_eq!(-123, entry.score);
+        assert_eq!(5, entry.depth);
+        assert_eq!(Bound::Lower, entry.bound);
+        assert_eq!(0, entry.generation);
+        // the best move is kept if the new result has none
+        tt.store(pos.key(), None, 456, -1, Bound::Upper);
+        let entry = tt.probe(pos.key()).expect("not found");
+        assert_eq!(Some(m), entry.best_move(pos.side_to_move()));
+        assert_eq!(
+            (456, -1, Bound::Upper),
+            (entry.score, entry.depth, entry.bound)
+        );
+        // drops
+        let m = Move::Drop {
+            to: Square::SQ_5E,
+            piece: Piece::W_R,
+        };
+        tt.store(1, Some(m), 0, 0, Bound::Exact);
+        assert_eq!(
+            Some(m),
+            tt.probe(1).and_then(|entry| entry.best_move(Color::White))
+        );
+
+        tt.clear();
+        assert_eq!(None, tt.probe(pos.key()));
+    }
+
+    #[test]
+    fn replacement() {
+        let tt = TranspositionTable::new(0);
+        assert_eq!(BUCKET_SIZE, tt.capacity());
+        for key in 1..=4 {
+            tt.store(key, None, 0, key as i32, Bound::Exact);
+        }
+        assert!((1..=4).all(|key| tt.probe(key).is_some()));
+        assert_eq!(1000, tt.hashfull());
+        // the shallowest one is replaced
+        tt.store(5, None, 0, 3, Bound::Exact);
+        assert_eq!(None, tt.probe(1));
+        // the older ones are replaced even if they're deeper
+        tt.new_search();
+        assert_eq!(0, tt.hashfull());
+        tt.store(6, None, 0, 1, Bound::Exact);
+        tt.store(7, None, 0, 1, Bound::Exact);
+        assert!(tt.probe(6).is_some() && tt.probe(7).is_some());
+        assert_eq!(500, tt.hashfull());
+    }
+
+    #[test]
+    fn concurrent() {
+        let tt = Arc::new(TranspositionTable::new(1));
+        let handles = (0..4)
+            .map(|i| {
+                let tt = Arc::clone(&tt);
+                thread::spawn(move || {
+                    for key in (0..10000u64).map(|k| k.wrapping_mul(0x9e37_79b9_7f4a_7c15)) {
+                        tt.store(key, None, i, i, Bound::Exact);
+                        // never torn
+                        if let Some(entry) = tt.probe(key) {
+                            assert_eq!(i32::from(entry.score), i32::from(entry.depth));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().expect("failed to join");
+        }
+    }
+}
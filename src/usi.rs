@@ -0,0 +1,410 @@
+//! Helpers of the [USI protocol](http://shogidokoro.starfree.jp/usi.html) for engines.
+use crate::Position;
+use shogi_core::{Color, Move, PartialPosition, Piece, ToUsi};
+use shogi_usi_parser::FromUsi;
+use std::fmt;
+use std::time::Duration;
+
+/// Parses the USI move such as `7g7f` or `P*5e`, and returns it if it is legal in the position.
+pub fn parse_move(pos: &Position, s: &str) -> Option<Move> {
+    let m = match Move::from_usi(s).ok()? {
+        // drops are parsed as black's
+        Move::Drop { piece, to } => Move::Drop {
+            piece: Piece::new(piece.piece_kind(), pos.side_to_move()),
+            to,
+        },
+        m => m,
+    };
+    pos.legal_moves().contains(&m).then_some(m)
+}
+
+/// Parses the `position` command such as `position startpos moves 7g7f 3c3d`
+/// or `position sfen <sfen> moves ...` (`position` may be omitted).
+/// Returns `None` if the position is invalid or any of the moves is illegal.
+pub fn parse_position(s: &str) -> Option<Position> {
+    let s = s.trim();
+    let s = s.strip_prefix("position").unwrap_or(s).trim_start();
+    let (sfen, moves) = match s.split_once(" moves") {
+        Some((sfen, moves)) => (sfen.trim_end(), moves),
+        None => (s, ""),
+    };
+    let mut pos = Position::new(PartialPosition::from_usi(sfen).ok()?);
+    for token in moves.split_whitespace() {
+        let m = parse_move(&pos, token)?;
+        pos.do_move(m);
+    }
+    Some(pos)
+}
+
+/// Formats the `position` command which reproduces the position with its moves,
+/// or returns `None` if a null move has been made, which can't be written in USI.
+pub fn format_position(pos: &Position) -> Option<String> {
+    if pos.has_null_move() {
+        return None;
+    }
+    let initial = pos.initial_position();
+    let mut s = if initial == PartialPosition::startpos() {
+        String::from("position startpos")
+    } else {
+        format!("position sfen {}", initial.to_sfen_owned())
+    };
+    let moves = pos.moves();
+    if !moves.is_empty() {
+        s.push_str(" moves");
+        for m in moves {
+            s.push(' ');
+            s.push_str(&m.to_usi_owned());
+        }
+    }
+    Some(s)
+}
+
+/// Time limit of `go mate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MateTime {
+    Limit(Duration),
+    Infinite,
+}
+
+/// Parameters of the `go` command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoParams {
+    pub btime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub byoyomi: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    /// `go mate`, which requests searching checkmates instead of the normal search.
+    pub mate: Option<MateTime>,
+    pub infinite: bool,
+    pub ponder: bool,
+    /// Moves in USI, which restrict the search.
+    pub searchmoves: Vec<String>,
+}
+
+impl GoParams {
+    /// Parses the `go` command such as `go btime 60000 wtime 60000 byoyomi 10000` (`go` may be omitted).
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut params = Self::default();
+        let mut tokens = s.split_whitespace().peekable();
+        if tokens.peek() == Some(&"go") {
+            tokens.next();
+        }
+        let millis = |token: Option<&str>| token?.parse().ok().map(Duration::from_millis);
+        while let Some(token) = tokens.next() {
+            match token {
+                "btime" => params.btime = Some(millis(tokens.next())?),
+                "wtime" => params.wtime = Some(millis(tokens.next())?),
+                "byoyomi" => params.byoyomi = Some(millis(tokens.next())?),
+                "binc" => params.binc = Some(millis(tokens.next())?),
+                "winc" => params.winc = Some(millis(tokens.next())?),
+                "depth" => params.depth = Some(tokens.next()?.parse().ok()?),
+                "nodes" => params.nodes = Some(tokens.next()?.parse().ok()?),
+                "mate" => {
+                    params.mate = Some(match tokens.next()? {
+                        "infinite" => MateTime::Infinite,
+                        time => MateTime::Limit(millis(Some(time))?),
+                    })
+                }
+                "infinite" => params.infinite = true,
+                "ponder" => params.ponder = true,
+                "searchmoves" => {
+                    while let Some(m) = tokens.next_if(|&t| Move::from_usi(t).is_ok()) {
+                        params.searchmoves.push(m.to_string());
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+    /// Returns the remaining time of the player.
+    pub fn time(&self, c: Color) -> Option<Duration> {
+        match c {
+            Color::Black => self.btime,
+            Color::White => self.wtime,
+        }
+    }
+    /// Returns the increment of the player.
+    pub fn inc(&self, c: Color) -> Option<Duration> {
+        match c {
+            Color::Black => self.binc,
+            Color::White => self.winc,
+        }
+    }
+}
+
+/// Score of the `info` command, from the side to move's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    /// Centipawns.
+    Cp(i32),
+    /// Plies to checkmate: positive if the side to move mates, negative if it is mated.
+    Mate(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Lower,
+    Upper,
+}
+
+/// The `info` command. Only the fields with values are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    /// Usage of the hash table in permill.
+    pub hashfull: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub bound: Option<Bound>,
+    pub currmove: Option<Move>,
+    pub pv: Vec<Move>,
+    pub string: Option<String>,
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("info")?;
+        if let Some(depth) = self.depth {
+            write!(f, " depth {depth}")?;
+            if let Some(seldepth) = self.seldepth {
+                write!(f, " seldepth {seldepth}")?;
+            }
+        }
+        if let Some(time) = self.time {
+            write!(f, " time {}", time.as_millis())?;
+        }
+        if let Some(nodes) = self.nodes {
+            write!(f, " nodes {nodes}")?;
+        }
+        if let Some(nps) = self.nps {
+            write!(f, " nps {nps}")?;
+        }
+        if let Some(hashfull) = self.hashfull {
+            write!(f, " hashfull {hashfull}")?;
+        }
+        if let Some(multipv) = self.multipv {
+            write!(f, " multipv {multipv}")?;
+        }
+        match self.score {
+            Some(Score::Cp(cp)) => write!(f, " score cp {cp}")?,
+            Some(Score::Mate(plies)) => write!(f, " score mate {plies}")?,
+            None => {}
+        }
+        match (self.score, self.bound) {
+            (Some(_), Some(Bound::Lower)) => f.write_str(" lowerbound")?,
+            (Some(_), Some(Bound::Upper)) => f.write_str(" upperbound")?,
+            _ => {}
+        }
+        if let Some(m) = self.currmove {
+            write!(f, " currmove {}", m.to_usi_owned())?;
+        }
+        // `pv` and `string` take the rest of the line
+        if !self.pv.is_empty() {
+            f.write_str(" pv")?;
+            for m in &self.pv {
+                write!(f, " {}", m.to_usi_owned())?;
+            }
+        } else if let Some(string) = &self.string {
+            write!(f, " string {string}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The `bestmove` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestMove {
+    Move(Move, Option<Move>),
+    Resign,
+    /// Declares the win by entering king.
+    Win,
+}
+
+impl fmt::Display for BestMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BestMove::Move(m, ponder) => {
+                write!(f, "bestmove {}", m.to_usi_owned())?;
+                if let Some(ponder) = ponder {
+                    write!(f, " ponder {}", ponder.to_usi_owned())?;
+                }
+                Ok(())
+            }
+            BestMove::Resign => f.write_str("bestmove resign"),
+            BestMove::Win => f.write_str("bestmove win"),
+        }
+    }
+}
+
+/// The `checkmate` command, which answers `go mate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checkmate {
+    Mate(Vec<Move>),
+    NoMate,
+    Timeout,
+    NotImplemented,
+}
+
+impl fmt::Display for Checkmate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("checkmate")?;
+        match self {
+            Checkmate::Mate(moves) => {
+                for m in moves {
+                    write!(f, " {}", m.to_usi_owned())?;
+                }
+                Ok(())
+            }
+            Checkmate::NoMate => f.write_str(" nomate"),
+            Checkmate::Timeout => f.write_str(" timeout"),
+            Checkmate::NotImplemented => f.write_str(" notimplemented"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shogi_core::Square;
+
+    #[test]
+    fn position() {
+        let pos = parse_position("position startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e")
+            .expect("failed to parse");
+        assert_eq!(Color::White, pos.side_to_move());
+        assert_eq!(6, pos.ply());
+        assert_eq!(Some(Piece::B_B), pos.piece_at(Square::SQ_4E));
+        assert_eq!(
+            Some("position startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e"),
+            format_position(&pos).as_deref()
+        );
+        let mut null = pos.clone();
+        null.do_null_move();
+        assert_eq!(None, format_position(&null));
+
+        // white's drop
+        assert!(parse_position("sfen 8k/9/9/9/9/9/9/9/K8 w P 1 moves P*5e").is_none());
+        let pos =
+            parse_position("sfen 8k/9/9/9/9/9/9/9/K8 w p 1 moves P*5e").expect("failed to parse");
+        assert_eq!(Some(Piece::W_P), pos.piece_at(Square::SQ_5E));
+        assert_eq!(
+            Some("position sfen 8k/9/9/9/9/9/9/9/K8 w p 1 moves P*5e"),
+            format_position(&pos).as_deref()
+        );
+        assert_eq!(
+            Some("position sfen 8k/9/9/9/9/9/9/9/K8 w p 1"),
+            format_position(&parse_position("position sfen 8k/9/9/9/9/9/9/9/K8 w p 1").unwrap())
+                .as_deref()
+        );
+
+        // illegal moves
+        assert!(parse_position("position startpos moves 7g7f 7g7f").is_none());
+        assert!(parse_position("position startpos moves 7g7e").is_none());
+        assert!(parse_position("position sfen invalid").is_none());
+    }
+
+    #[test]
+    fn go() {
+        assert_eq!(
+            Some(GoParams {
+                btime: Some(Duration::from_secs(60)),
+                wtime: Some(Duration::from_secs(50)),
+                byoyomi: Some(Duration::from_secs(10)),
+                ..Default::default()
+            }),
+            GoParams::parse("go btime 60000 wtime 50000 byoyomi 10000")
+        );
+        let params = GoParams::parse("go ponder btime 1000 wtime 2000 binc 100 winc 200")
+            .expect("failed to parse");
+        assert!(params.ponder);
+        assert_eq!(Some(Duration::from_secs(2)), params.time(Color::White));
+        assert_eq!(Some(Duration::from_millis(100)), params.inc(Color::Black));
+        assert_eq!(
+            Some(MateTime::Infinite),
+            GoParams::parse("go mate infinite").and_then(|params| params.mate)
+        );
+        assert_eq!(
+            Some(MateTime::Limit(Duration::from_secs(3))),
+            GoParams::parse("go mate 3000").and_then(|params| params.mate)
+        );
+        let params =
+            GoParams::parse("go infinite searchmoves 7g7f P*5e depth 3").expect("failed to parse");
+        assert!(params.infinite);
+        assert_eq!(vec!["7g7f", "P*5e"], params.searchmoves);
+        assert_eq!(Some(3), params.depth);
+        assert_eq!(None, GoParams::parse("go btime"));
+        assert_eq!(None, GoParams::parse("go unknown"));
+    }
+
+    #[test]
+    fn info() {
+        let info = Info {
+            depth: Some(10),
+            seldepth: Some(14),
+            time: Some(Duration::from_millis(1234)),
+            nodes: Some(100000),
+            nps: Some(81037),
+            hashfull: Some(12),
+            score: Some(Score::Cp(-30)),
+            bound: Some(Bound::Lower),
+            pv: vec![
+                Move::Normal {
+                    from: Square::SQ_7G,
+                    to: Square::SQ_7F,
+                    promote: false,
+                },
+                Move::Drop {
+                    to: Square::SQ_5E,
+                    piece: Piece::W_P,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            "info depth 10 seldepth 14 time 1234 nodes 100000 nps 81037 hashfull 12 score cp -30 lowerbound pv 7g7f P*5e",
+            info.to_string()
+        );
+        let info = Info {
+            score: Some(Score::Mate(-3)),
+            string: Some(String::from("mated")),
+            ..Default::default()
+        };
+        assert_eq!("info score mate -3 string mated", info.to_string());
+    }
+
+    #[test]
+    fn bestmove() {
+        let m = Move::Normal {
+            from: Square::SQ_8H,
+            to: Square::SQ_2B,
+            promote: true,
+        };
+        let ponder = Move::Normal {
+            from: Square::SQ_3A,
+            to: Square::SQ_2B,
+            promote: false,
+        };
+        assert_eq!("bestmove 8h2b+", BestMove::Move(m, None).to_string());
+        assert_eq!(
+            "bestmove 8h2b+ ponder 3a2b",
+            BestMove::Move(m, Some(ponder)).to_string()
+        );
+        assert_eq!("bestmove resign", BestMove::Resign.to_string());
+        assert_eq!("bestmove win", BestMove::Win.to_string());
+
+        assert_eq!(
+            "checkmate 8h2b+ 3a2b",
+            Checkmate::Mate(vec![m, ponder]).to_string()
+        );
+        assert_eq!("checkmate nomate", Checkmate::NoMate.to_string());
+        assert_eq!("checkmate timeout", Checkmate::Timeout.to_string());
+    }
+}